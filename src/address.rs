@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use log::{debug, warn};
+use tiny_keccak::{Hasher, Keccak};
+use crate::CgCoin;
+
+/// Platforms whose addresses are base58 and so case-sensitive whatever they look like.
+const BASE58_PLATFORMS: [&str; 1] = ["solana"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressKind {
+    /// `0x` and 40 hex digits, case-insensitive, checksummed by EIP-55 when mixed-case.
+    Evm,
+    /// Case-sensitive base58.
+    Base58,
+    /// Anything else, compared as is.
+    Other,
+}
+
+pub fn address_kind(platform: &str, address: &str) -> AddressKind {
+    if BASE58_PLATFORMS.contains(&platform) {
+        AddressKind::Base58
+    } else if is_evm_address(address) {
+        AddressKind::Evm
+    } else {
+        AddressKind::Other
+    }
+}
+
+/// Index key of an address, `None` for empty ones. EVM addresses are lowercased.
+pub fn normalize_address(platform: &str, address: &str) -> Option<String> {
+    let address = address.trim();
+    if address.is_empty() {
+        return None;
+    }
+    match address_kind(platform, address) {
+        AddressKind::Evm => Some(address.to_ascii_lowercase()),
+        AddressKind::Base58 | AddressKind::Other => Some(address.to_string()),
+    }
+}
+
+/// EIP-55 form of an EVM address.
+pub fn to_checksum_address(address: &str) -> String {
+    let hex = address[2..].to_ascii_lowercase();
+    let mut hasher = Keccak::v256();
+    hasher.update(hex.as_bytes());
+    let mut hash = [0_u8; 32];
+    hasher.finalize(&mut hash);
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// All-lowercase and all-uppercase addresses carry no checksum and pass.
+pub fn is_valid_checksum(address: &str) -> bool {
+    let hex = &address[2..];
+    if hex == hex.to_ascii_lowercase() || hex == hex.to_ascii_uppercase() {
+        return true;
+    }
+    // `0X` is a valid prefix too, only the digits carry the checksum.
+    to_checksum_address(address)[2..] == *hex
+}
+
+fn is_evm_address(address: &str) -> bool {
+    address.len() == 42
+        && (address.starts_with("0x") || address.starts_with("0X"))
+        && address[2..].bytes().all(|x| x.is_ascii_hexdigit())
+}
+
+/// Same address listed for more than one coin.
+#[derive(Debug)]
+pub struct DuplicateAddress {
+    pub platform: String,
+    pub address: String,
+    pub ids: Vec<String>,
+}
+
+/// `(platform, normalized address)` to the ids of coins listing it.
+#[derive(Debug, Default)]
+pub struct AddressIndex {
+    ids: HashMap<(String, String), Vec<String>>,
+    /// Null or empty addresses skipped while building.
+    pub empty_num: usize,
+}
+
+impl AddressIndex {
+    pub fn new(coins: &[CgCoin]) -> Self {
+        let mut index = Self::default();
+        for coin in coins {
+            for (platform, address) in &coin.platforms {
+                let address = match address {
+                    Some(x) if !platform.is_empty() => normalize_address(platform, x),
+                    _ => None,
+                };
+                let Some(address) = address else {
+                    index.empty_num += 1;
+                    continue;
+                };
+                let ids = index.ids.entry((platform.clone(), address)).or_default();
+                if !ids.contains(&coin.id) {
+                    ids.push(coin.id.clone());
+                }
+            }
+        }
+        let duplicates = index.duplicates();
+        if !duplicates.is_empty() {
+            warn!("{} contract addresses are listed for more than one coin.", duplicates.len());
+            for x in &duplicates {
+                debug!("{} {}: {}", x.platform, x.address, x.ids.join(", "));
+            }
+        }
+        index
+    }
+
+    /// Ids of coins listing `address` on `platform`, more than one for duplicates.
+    pub fn get(&self, platform: &str, address: &str) -> &[String] {
+        let Some(address) = normalize_address(platform, address) else {
+            return &[];
+        };
+        self.ids
+            .get(&(platform.to_string(), address))
+            .map_or(&[], |x| x.as_slice())
+    }
+
+    pub fn duplicates(&self) -> Vec<DuplicateAddress> {
+        let mut duplicates: Vec<DuplicateAddress> = self
+            .ids
+            .iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|((platform, address), ids)| DuplicateAddress {
+                platform: platform.clone(),
+                address: address.clone(),
+                ids: ids.clone(),
+            })
+            .collect();
+        duplicates.sort_by(|a, b| (&a.platform, &a.address).cmp(&(&b.platform, &b.address)));
+        duplicates
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+}
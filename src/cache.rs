@@ -0,0 +1,380 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use hyper::HeaderMap;
+use hyper::header::{HeaderValue, CACHE_CONTROL, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use log::warn;
+
+/// Response body with the validators needed to revalidate it later.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    #[serde(skip)]
+    pub body: Bytes,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Unix seconds until which the entry can be used without asking the server.
+    pub expires_at: Option<u64>,
+}
+
+impl CachedResponse {
+    /// Builds an entry from a 200 response, `None` if the response is not cacheable.
+    pub fn from_response(body: Bytes, headers: &HeaderMap<HeaderValue>) -> Option<Self> {
+        let cache_control = CacheControl::parse(headers);
+        if cache_control.no_store {
+            return None;
+        }
+        let entry = Self {
+            body,
+            etag: header_string(headers, ETAG),
+            last_modified: header_string(headers, LAST_MODIFIED),
+            expires_at: cache_control.expires_at(now_unix()),
+        };
+        if entry.etag.is_none() && entry.last_modified.is_none() && entry.expires_at.is_none() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Entry that never expires, for responses that can't change.
+    pub fn permanent(body: Bytes, headers: &HeaderMap<HeaderValue>) -> Self {
+        Self {
+            body,
+            etag: header_string(headers, ETAG),
+            last_modified: header_string(headers, LAST_MODIFIED),
+            expires_at: Some(u64::MAX),
+        }
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        self.expires_at == Some(u64::MAX)
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.expires_at.is_some_and(|x| x > now_unix())
+    }
+
+    /// Expired and without validators, so it can neither be served nor revalidated.
+    pub fn is_dead(&self) -> bool {
+        !self.is_fresh() && self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Applies the headers of a 304 response: new validators and a new freshness lifetime.
+    pub fn revalidate(&mut self, headers: &HeaderMap<HeaderValue>) {
+        if let Some(etag) = header_string(headers, ETAG) {
+            self.etag = Some(etag);
+        }
+        if let Some(last_modified) = header_string(headers, LAST_MODIFIED) {
+            self.last_modified = Some(last_modified);
+        }
+        self.expires_at = CacheControl::parse(headers).expires_at(now_unix());
+    }
+}
+
+#[derive(Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap<HeaderValue>) -> Self {
+        let mut res = Self::default();
+        for value in headers.get_all(CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for directive in value.split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", seconds)) => res.max_age = seconds.trim_matches('"').parse().ok(),
+                    None if directive == "no-store" => res.no_store = true,
+                    None if directive == "no-cache" => res.no_cache = true,
+                    _ => {}
+                }
+            }
+        }
+        res
+    }
+
+    fn expires_at(&self, now: u64) -> Option<u64> {
+        if self.no_cache {
+            return None;
+        }
+        self.max_age.filter(|x| *x > 0).map(|x| now.saturating_add(x))
+    }
+}
+
+pub trait ResponseCache: Send {
+    fn get(&mut self, key: &str) -> Option<CachedResponse>;
+    fn put(&mut self, key: &str, response: CachedResponse);
+}
+
+/// Use order of cache keys, for least-recently-used eviction. Permanent keys are never
+/// evicted and don't count against the limit.
+#[derive(Default)]
+struct Lru {
+    clock: u64,
+    used_at: HashMap<String, u64>,
+    permanent: HashSet<String>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str, permanent: bool) {
+        if permanent {
+            self.used_at.remove(key);
+            self.permanent.insert(key.to_string());
+            return;
+        }
+        self.permanent.remove(key);
+        self.clock += 1;
+        self.used_at.insert(key.to_string(), self.clock);
+    }
+    fn remove(&mut self, key: &str) {
+        self.used_at.remove(key);
+        self.permanent.remove(key);
+    }
+    /// Forgets and returns the least recently used keys beyond `max`.
+    fn evict(&mut self, max: usize) -> Vec<String> {
+        if self.used_at.len() <= max {
+            return vec![];
+        }
+        let mut keys: Vec<(u64, String)> =
+            self.used_at.iter().map(|(k, v)| (*v, k.clone())).collect();
+        keys.sort();
+        keys.truncate(self.used_at.len() - max);
+        for (_, key) in &keys {
+            self.used_at.remove(key);
+        }
+        keys.into_iter().map(|(_, key)| key).collect()
+    }
+}
+
+/// Keeps at most `max_entries` expiring entries plus the permanent ones, dead ones are
+/// dropped on every `put`.
+pub struct MemoryResponseCache {
+    items: HashMap<String, CachedResponse>,
+    lru: Lru,
+    max_entries: usize,
+}
+
+impl MemoryResponseCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            items: HashMap::new(),
+            lru: Lru::default(),
+            max_entries,
+        }
+    }
+}
+
+impl ResponseCache for MemoryResponseCache {
+    fn get(&mut self, key: &str) -> Option<CachedResponse> {
+        let entry = self.items.get(key)?;
+        if entry.is_dead() {
+            self.items.remove(key);
+            self.lru.remove(key);
+            return None;
+        }
+        self.lru.touch(key, entry.is_permanent());
+        Some(entry.clone())
+    }
+    fn put(&mut self, key: &str, response: CachedResponse) {
+        self.lru.touch(key, response.is_permanent());
+        self.items.insert(key.to_string(), response);
+        let lru = &mut self.lru;
+        self.items.retain(|key, x| {
+            let dead = x.is_dead();
+            if dead {
+                lru.remove(key);
+            }
+            !dead
+        });
+        for key in self.lru.evict(self.max_entries) {
+            self.items.remove(&key);
+        }
+    }
+}
+
+/// Stores every entry as a `<hash>.json` meta file plus a `<hash>.body` file, at most
+/// `max_entries` of them. Files are replaced via a temporary file and rename, and the meta
+/// file goes first and comes back last, so a crash leaves no validators next to another body.
+/// Permanent entries don't count against `max_entries`.
+/// I/O failures are logged and treated as cache misses.
+pub struct DiskResponseCache {
+    dir: PathBuf,
+    lru: Lru,
+    /// Unix seconds after which entries without validators are dead, by file name.
+    dead_at: HashMap<String, u64>,
+    max_entries: usize,
+}
+
+impl DiskResponseCache {
+    /// Indexes the entries left by a previous run, oldest first, and removes broken ones.
+    pub fn new(dir: PathBuf, max_entries: usize) -> Result<Self, String> {
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Can't create cache dir {}: {}", dir.display(), e))?;
+        let mut cache = Self {
+            dir,
+            lru: Lru::default(),
+            dead_at: HashMap::new(),
+            max_entries,
+        };
+        let read_dir = fs::read_dir(&cache.dir)
+            .map_err(|e| format!("Can't read cache dir {}: {}", cache.dir.display(), e))?;
+        let mut found = vec![];
+        for path in read_dir.flatten().map(|x| x.path()) {
+            let (Some(name), Some(extension)) = (path.file_stem(), path.extension()) else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            match extension.to_str() {
+                Some("json") => {
+                    let meta = fs::read(&path)
+                        .ok()
+                        .and_then(|x| serde_json::from_slice::<CachedResponse>(&x).ok());
+                    match meta {
+                        Some(meta) if cache.body_path(&name).exists() => {
+                            let modified = path.metadata().and_then(|x| x.modified()).ok();
+                            found.push((modified, name, meta));
+                        }
+                        _ => cache.remove_files(&name),
+                    }
+                }
+                Some("body") if !cache.meta_path(&name).exists() => cache.remove_files(&name),
+                Some("tmp") => {
+                    let _ = fs::remove_file(&path);
+                }
+                _ => {}
+            }
+        }
+        found.sort_by_key(|(modified, _, _)| *modified);
+        for (_, name, meta) in found {
+            cache.track(&name, &meta);
+        }
+        cache.sweep();
+        Ok(cache)
+    }
+    fn name(key: &str) -> String {
+        format!("{:016x}", fnv1a(key.as_bytes()))
+    }
+    fn meta_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+    fn body_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.body", name))
+    }
+    fn track(&mut self, name: &str, meta: &CachedResponse) {
+        self.lru.touch(name, meta.is_permanent());
+        match meta.expires_at {
+            Some(x) if meta.etag.is_none() && meta.last_modified.is_none() => {
+                self.dead_at.insert(name.to_string(), x);
+            }
+            _ => {
+                self.dead_at.remove(name);
+            }
+        }
+    }
+    fn remove_files(&mut self, name: &str) {
+        self.lru.remove(name);
+        self.dead_at.remove(name);
+        for path in [self.meta_path(name), self.body_path(name)] {
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Can't remove cache file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    /// Removes dead entries, then the least recently used ones beyond `max_entries`.
+    fn sweep(&mut self) {
+        let now = now_unix();
+        let dead: Vec<String> = self
+            .dead_at
+            .iter()
+            .filter(|(_, x)| **x <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in dead {
+            self.remove_files(&name);
+        }
+        for name in self.lru.evict(self.max_entries) {
+            self.remove_files(&name);
+        }
+    }
+}
+
+impl ResponseCache for DiskResponseCache {
+    fn get(&mut self, key: &str) -> Option<CachedResponse> {
+        let name = Self::name(key);
+        let meta = fs::read(self.meta_path(&name)).ok()?;
+        let mut entry: CachedResponse = match serde_json::from_slice(&meta) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Broken cache entry for {}: {}", key, e);
+                self.remove_files(&name);
+                return None;
+            }
+        };
+        if entry.is_dead() {
+            self.remove_files(&name);
+            return None;
+        }
+        entry.body = Bytes::from(fs::read(self.body_path(&name)).ok()?);
+        self.lru.touch(&name, entry.is_permanent());
+        Some(entry)
+    }
+    fn put(&mut self, key: &str, response: CachedResponse) {
+        let name = Self::name(key);
+        let (meta_path, body_path) = (self.meta_path(&name), self.body_path(&name));
+        let meta = serde_json::to_vec(&response).unwrap();
+        let res = match fs::remove_file(&meta_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => write_replacing(&body_path, &response.body)
+                .and_then(|_| write_replacing(&meta_path, &meta)),
+        };
+        match res {
+            Ok(()) => self.track(&name, &response),
+            Err(e) => warn!("Can't write cache entry for {}: {}", key, e),
+        }
+        self.sweep();
+    }
+}
+
+fn write_replacing(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, path))
+}
+
+/// Picks the on-disk backend when a cache dir is configured, in-memory otherwise.
+pub fn create_response_cache(
+    dir: Option<&PathBuf>,
+    max_entries: usize,
+) -> Result<Box<dyn ResponseCache>, String> {
+    match dir {
+        Some(dir) => Ok(Box::new(DiskResponseCache::new(dir.clone(), max_entries)?)),
+        None => Ok(Box::new(MemoryResponseCache::new(max_entries))),
+    }
+}
+
+fn header_string(headers: &HeaderMap<HeaderValue>, name: hyper::header::HeaderName) -> Option<String> {
+    headers.get(name)?.to_str().ok().map(|x| x.to_string())
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Stable across builds, unlike `DefaultHasher`, so file names survive upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,245 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::numeric::{parse_number, NumericConfig, NumericValue};
+
+/// Point spacing CoinGecko picks by the requested span: up to 90 days hourly, longer daily.
+/// 5-minutely points are only kept for the last day, older spans of a day come hourly.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Granularity {
+    FiveMinutely,
+    Hourly,
+    Daily,
+}
+
+impl Granularity {
+    /// Longest span of one request that still comes back at this granularity.
+    fn max_span(&self) -> Option<Duration> {
+        match self {
+            Granularity::FiveMinutely => Some(Duration::days(1)),
+            Granularity::Hourly => Some(Duration::days(90)),
+            Granularity::Daily => None,
+        }
+    }
+
+    /// Range starts before this come back coarser whatever the span, `None` when any is fine.
+    pub fn earliest_from(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Granularity::FiveMinutely => Some(now - Duration::days(1)),
+            Granularity::Hourly | Granularity::Daily => None,
+        }
+    }
+
+    /// Shortest span that comes back at this granularity, shorter chunks are widened.
+    fn min_span(&self) -> Duration {
+        match self {
+            Granularity::FiveMinutely => Duration::zero(),
+            Granularity::Hourly => Duration::days(1) + Duration::hours(1),
+            Granularity::Daily => Duration::days(91),
+        }
+    }
+}
+
+/// `from`/`to` of every `/market_chart/range` request covering `[from, to]` at `granularity`.
+pub fn chunk_range(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    granularity: Granularity,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut chunks = vec![];
+    let mut start = from;
+    while start < to {
+        let end = match granularity.max_span() {
+            Some(span) => (start + span).min(to),
+            None => to,
+        };
+        chunks.push(((end - granularity.min_span()).min(start), end));
+        start = end;
+    }
+    chunks
+}
+
+/// `/market_chart` response, points are `[unix ms, value]`.
+#[derive(Deserialize, Debug, Default)]
+pub struct CgRawMarketChart {
+    #[serde(default)]
+    prices: Vec<(serde_json::Value, serde_json::Value)>,
+    #[serde(default)]
+    market_caps: Vec<(serde_json::Value, serde_json::Value)>,
+    #[serde(default)]
+    total_volumes: Vec<(serde_json::Value, serde_json::Value)>,
+}
+
+pub type Series = Vec<(DateTime<Utc>, Decimal)>;
+
+/// Time-sorted series without duplicate timestamps.
+#[derive(Serialize, Debug, Default)]
+pub struct MarketChart {
+    pub prices: Series,
+    pub market_caps: Series,
+    pub total_volumes: Series,
+}
+
+impl MarketChart {
+    /// Adds the points of `raw` within `[from, to]`, points with `null` or unparsable values are skipped.
+    pub fn merge(
+        &mut self,
+        raw: CgRawMarketChart,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        numeric: &NumericConfig,
+    ) {
+        let in_range =
+            |x: &DateTime<Utc>| from.is_none_or(|from| *x >= from) && to.is_none_or(|to| *x <= to);
+        let series = [
+            (&mut self.prices, raw.prices),
+            (&mut self.market_caps, raw.market_caps),
+            (&mut self.total_volumes, raw.total_volumes),
+        ];
+        for (target, points) in series {
+            target.extend(
+                points
+                    .into_iter()
+                    .filter_map(|(timestamp, value)| to_point(&timestamp, &value, numeric))
+                    .filter(|(timestamp, _)| in_range(timestamp)),
+            );
+            target.sort_by_key(|(timestamp, _)| *timestamp);
+            target.dedup_by_key(|(timestamp, _)| *timestamp);
+        }
+    }
+}
+
+fn to_point(
+    timestamp: &serde_json::Value,
+    value: &serde_json::Value,
+    numeric: &NumericConfig,
+) -> Option<(DateTime<Utc>, Decimal)> {
+    let ms = timestamp.as_i64().or(timestamp.as_f64().map(|x| x as i64))?;
+    let timestamp = DateTime::from_timestamp_millis(ms)?;
+    let serde_json::Value::Number(number) = value else {
+        return None;
+    };
+    match parse_number(&number.to_string(), numeric) {
+        Ok(NumericValue::Decimal(x)) => Some((timestamp, x)),
+        _ => None,
+    }
+}
+
+/// `days` values `/coins/{id}/ohlc` accepts, candles are 30 minutes up to 2 days,
+/// 4 hours up to 30 days and 4 days beyond.
+pub const OHLC_DAYS: &[&str] = &["1", "7", "14", "30", "90", "180", "365", "max"];
+
+#[derive(Clone, Copy, Debug, strum_macros::Display, clap::ValueEnum)]
+pub enum OhlcDays {
+    #[strum(serialize = "1")]
+    #[value(name = "1")]
+    D1,
+    #[strum(serialize = "7")]
+    #[value(name = "7")]
+    D7,
+    #[strum(serialize = "14")]
+    #[value(name = "14")]
+    D14,
+    #[strum(serialize = "30")]
+    #[value(name = "30")]
+    D30,
+    #[strum(serialize = "90")]
+    #[value(name = "90")]
+    D90,
+    #[strum(serialize = "180")]
+    #[value(name = "180")]
+    D180,
+    #[strum(serialize = "365")]
+    #[value(name = "365")]
+    D365,
+    #[strum(serialize = "max")]
+    #[value(name = "max")]
+    Max,
+}
+
+/// `/ohlc` response, candles are `[unix ms, open, high, low, close]`.
+pub type CgRawOhlc = Vec<Vec<serde_json::Value>>;
+
+/// Candle ending at `timestamp`.
+#[derive(Serialize, Debug)]
+pub struct Candle {
+    pub timestamp: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+}
+
+/// Time-sorted candles, malformed ones are skipped.
+pub fn to_candles(raw: CgRawOhlc, numeric: &NumericConfig) -> Vec<Candle> {
+    let mut candles: Vec<Candle> = raw
+        .iter()
+        .filter_map(|x| {
+            let [timestamp, open, high, low, close] = x.as_slice() else {
+                return None;
+            };
+            let value = |x| to_point(timestamp, x, numeric).map(|(_, x)| x);
+            Some(Candle {
+                timestamp: to_point(timestamp, open, numeric)?.0,
+                open: value(open)?,
+                high: value(high)?,
+                low: value(low)?,
+                close: value(close)?,
+            })
+        })
+        .collect();
+    candles.sort_by_key(|x| x.timestamp);
+    candles
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn from() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn hourly_within_max_span() {
+        let (from, to) = (from(), from() + Duration::days(10));
+        assert_eq!(chunk_range(from, to, Granularity::Hourly), vec![(from, to)]);
+        let to = from + Duration::hours(6);
+        let widened = to - Duration::days(1) - Duration::hours(1);
+        assert_eq!(chunk_range(from, to, Granularity::Hourly), vec![(widened, to)]);
+    }
+
+    #[test]
+    fn hourly_beyond_max_span() {
+        let from = from();
+        let (a, b) = (from + Duration::days(90), from + Duration::days(180));
+        let to = b + Duration::days(20);
+        assert_eq!(
+            chunk_range(from, to, Granularity::Hourly),
+            vec![(from, a), (a, b), (b, to)],
+        );
+        // A short last chunk is widened back into the previous one.
+        let to = b + Duration::hours(12);
+        let widened = to - Duration::days(1) - Duration::hours(1);
+        assert_eq!(
+            chunk_range(from, to, Granularity::Hourly),
+            vec![(from, a), (a, b), (widened, to)],
+        );
+    }
+
+    #[test]
+    fn daily_is_one_chunk() {
+        let from = from();
+        let to = from + Duration::days(10);
+        let widened = to - Duration::days(91);
+        assert_eq!(chunk_range(from, to, Granularity::Daily), vec![(widened, to)]);
+        let to = from + Duration::days(400);
+        assert_eq!(chunk_range(from, to, Granularity::Daily), vec![(from, to)]);
+    }
+
+    #[test]
+    fn empty_range() {
+        assert_eq!(chunk_range(from(), from(), Granularity::Hourly), vec![]);
+    }
+}
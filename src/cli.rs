@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use crate::registry::CoinMetadata;
+use crate::search::SearchHit;
+use crate::chart::{Candle, Granularity, MarketChart, OhlcDays};
+use crate::{
+    CgAssetPlatform, CgCoin, CgCoinHistory, CgExchangeRates, CgGlobal, CgMarket, CgRates, CgTrending,
+    MarketsOrder,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Coingecko rate requester")]
+pub struct Cli {
+    /// TOML config file, `CG_REQ_CONFIG` or `cg_req.toml` by default.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Config override, e.g. `--set client.rates_tokens_per_request=250`.
+    #[arg(long, global = true, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+    /// Output format of printed coins and rates.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub format: OutputFormat,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Keep coins and rates in sync forever (default).
+    Sync,
+    /// Run a single sync cycle, write the sinks, print the coins with rates and exit.
+    Once,
+    /// Print the coin list.
+    Coins,
+    /// Print rates of the given coin ids.
+    Price {
+        #[arg(required = true)]
+        ids: Vec<String>,
+        /// Comma separated quote currencies, `rates.vs_currencies` by default.
+        #[arg(long, value_delimiter = ',')]
+        vs: Option<Vec<String>>,
+    },
+    /// Print prices of contract addresses on a platform, listed in the coin list or not.
+    TokenPrice {
+        /// Platform id, e.g. `ethereum`.
+        #[arg(long)]
+        platform: String,
+        #[arg(required = true)]
+        addresses: Vec<String>,
+        /// Comma separated quote currencies, `rates.vs_currencies` by default.
+        #[arg(long, value_delimiter = ',')]
+        vs: Option<Vec<String>>,
+    },
+    /// Print `/coins/markets`, text page by page, JSON as one array once every page is in.
+    Markets {
+        /// Quote currency, the first of `rates.vs_currencies` by default.
+        #[arg(long)]
+        vs: Option<String>,
+        #[arg(long, value_enum, default_value_t = MarketsOrder::MarketCapDesc)]
+        order: MarketsOrder,
+        #[arg(long, default_value_t = 250, value_parser = clap::value_parser!(u32).range(1..=250))]
+        per_page: u32,
+        /// Stop after this many pages, all of them by default.
+        #[arg(long)]
+        pages: Option<u32>,
+    },
+    /// Print price, market cap and volume series of a coin.
+    #[command(group = clap::ArgGroup::new("period").required(true))]
+    Chart {
+        id: String,
+        /// Quote currency, the first of `rates.vs_currencies` by default.
+        #[arg(long)]
+        vs: Option<String>,
+        /// Last N days or `max`, granularity picked by CoinGecko.
+        #[arg(long, group = "period")]
+        days: Option<String>,
+        /// Range start, e.g. `2024-01-01T00:00:00Z`.
+        #[arg(long, group = "period")]
+        from: Option<DateTime<Utc>>,
+        /// Range end, now by default.
+        #[arg(long, requires = "from")]
+        to: Option<DateTime<Utc>>,
+        /// Point spacing of a range, long ranges are requested in chunks to keep it.
+        /// `five-minutely` only within the last day.
+        #[arg(long, value_enum, default_value_t = Granularity::Daily)]
+        granularity: Granularity,
+    },
+    /// Print BTC exchange rates against fiat, crypto and commodities.
+    ExchangeRates,
+    /// Print total market cap, volume and dominance.
+    Global,
+    /// Print the most searched coins, NFTs and categories.
+    Trending,
+    /// Find coin ids by name, symbol or id, best match first.
+    Search {
+        /// E.g. `eth` or `wrapped bitcoin`, typos are tolerated from 4 characters.
+        query: String,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Print categories, links, genesis date and token decimals of a coin.
+    Info {
+        id: String,
+    },
+    /// Print OHLC candles of a coin.
+    Ohlc {
+        id: String,
+        /// Quote currency, the first of `rates.vs_currencies` by default.
+        #[arg(long)]
+        vs: Option<String>,
+        #[arg(long, value_enum)]
+        days: OhlcDays,
+    },
+    /// Print a coin's price, market cap and volume at 00:00 UTC of a past day.
+    History {
+        id: String,
+        /// Day, e.g. `2024-01-31`.
+        #[arg(long)]
+        date: NaiveDate,
+    },
+    /// Print the asset platforms with their EVM chain ids.
+    Platforms,
+    /// Find coins on a platform, by a contract address when given.
+    #[command(group = clap::ArgGroup::new("chain").required(true))]
+    Lookup {
+        /// Platform id, e.g. `ethereum`.
+        #[arg(long, group = "chain")]
+        platform: Option<String>,
+        /// EVM chain id, e.g. `137`, resolved through `/asset_platforms`.
+        #[arg(long, group = "chain")]
+        chain_id: Option<u64>,
+        /// Contract address, EVM ones match in any case.
+        #[arg(long)]
+        address: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+pub fn print_coins(coins: &[CgCoin], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for coin in coins {
+                let mut rates: Vec<String> = coin
+                    .rates
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .chain(coin.exact_rates.iter().map(|(k, v)| format!("{}={}", k, v)))
+                    .collect();
+                rates.sort();
+                if rates.is_empty() {
+                    match coin.rate_status {
+                        Some(x) => rates.push(format!("{:?}", x)),
+                        None => rates.push("-".to_string()),
+                    }
+                }
+                println!("{}\t{}\t{}\t{}", coin.id, coin.symbol, coin.name, rates.join(","));
+            }
+        }
+        OutputFormat::Json => print_json(&coins),
+    }
+}
+
+pub fn print_rates(rates: &CgRates, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let mut ids: Vec<&String> = rates.keys().collect();
+            ids.sort();
+            for id in ids {
+                let rate = &rates[id];
+                let mut quotes: Vec<String> = rate
+                    .prices
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .chain(rate.other.iter().map(|(k, v)| format!("{}={}", k, v)))
+                    .chain(rate.last_updated_at.map(|x| format!("updated={}", x.to_rfc3339())))
+                    .collect();
+                quotes.sort();
+                println!("{}\t{}", id, quotes.join("\t"));
+            }
+        }
+        OutputFormat::Json => print_json(rates),
+    }
+}
+
+pub fn print_platforms(platforms: &[CgAssetPlatform], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for platform in platforms {
+                let chain_id = platform.chain_identifier.map_or("-".to_string(), |x| x.to_string());
+                let native_coin_id = platform.native_coin_id.as_deref().unwrap_or("-");
+                println!("{}\t{}\t{}\t{}", platform.id, chain_id, platform.name, native_coin_id);
+            }
+        }
+        OutputFormat::Json => print_json(platforms),
+    }
+}
+
+/// Text is one line per coin, JSON an array.
+pub fn print_markets(markets: &[CgMarket], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for market in markets {
+                let rank = market.market_cap_rank.map_or("-".to_string(), |x| x.to_string());
+                let price = market.current_price.map_or("-".to_string(), |x| x.to_string());
+                let market_cap = market.market_cap.map_or("-".to_string(), |x| x.to_string());
+                println!("{}\t{}\t{}\t{}\t{}", rank, market.id, market.symbol, price, market_cap);
+            }
+        }
+        OutputFormat::Json => print_json(markets),
+    }
+}
+
+/// Text is `series timestamp value` per line.
+pub fn print_chart(chart: &MarketChart, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let series = [
+                ("price", &chart.prices),
+                ("market_cap", &chart.market_caps),
+                ("total_volume", &chart.total_volumes),
+            ];
+            for (name, points) in series {
+                for (timestamp, value) in points {
+                    println!("{}\t{}\t{}", name, timestamp.to_rfc3339(), value);
+                }
+            }
+        }
+        OutputFormat::Json => print_json(chart),
+    }
+}
+
+/// Text is `vs price market_cap volume` per vs currency.
+pub fn print_history(history: &CgCoinHistory, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let Some(market_data) = &history.market_data else {
+                println!("{}\tno market data", history.id);
+                return;
+            };
+            let mut vs_currencies: Vec<&String> = market_data.current_price.keys().collect();
+            vs_currencies.sort();
+            for vs in vs_currencies {
+                let value = |x: &HashMap<String, Decimal>| {
+                    x.get(vs).map_or("-".to_string(), |x| x.to_string())
+                };
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    vs,
+                    value(&market_data.current_price),
+                    value(&market_data.market_cap),
+                    value(&market_data.total_volume)
+                );
+            }
+        }
+        OutputFormat::Json => print_json(history),
+    }
+}
+
+/// Text is `timestamp open high low close` per line.
+pub fn print_candles(candles: &[Candle], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for x in candles {
+                let timestamp = x.timestamp.to_rfc3339();
+                println!("{}\t{}\t{}\t{}\t{}", timestamp, x.open, x.high, x.low, x.close);
+            }
+        }
+        OutputFormat::Json => print_json(candles),
+    }
+}
+
+pub fn print_metadata(metadata: &CoinMetadata, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let genesis_date = metadata.genesis_date.map_or("-".to_string(), |x| x.to_string());
+            let mut decimals: Vec<String> = metadata
+                .decimals
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v.map_or("-".to_string(), |x| x.to_string())))
+                .collect();
+            decimals.sort();
+            println!("id\t{}", metadata.id);
+            println!("categories\t{}", metadata.categories.join(", "));
+            println!("homepage\t{}", metadata.homepage.join(" "));
+            println!("explorers\t{}", metadata.explorers.join(" "));
+            println!("genesis_date\t{}", genesis_date);
+            println!("decimals\t{}", decimals.join(","));
+            if let Some(image) = metadata.image.get("large") {
+                println!("image\t{}", image);
+            }
+        }
+        OutputFormat::Json => print_json(metadata),
+    }
+}
+
+pub fn print_exchange_rates(rates: &CgExchangeRates, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let mut ids: Vec<&String> = rates.rates.keys().collect();
+            ids.sort();
+            for id in ids {
+                let rate = &rates.rates[id];
+                let value = rate.value.map_or("-".to_string(), |x| x.to_string());
+                println!("{}\t{}\t{}\t{}", id, rate.kind, value, rate.name);
+            }
+        }
+        OutputFormat::Json => print_json(rates),
+    }
+}
+
+pub fn print_global(global: &CgGlobal, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            let sorted = |x: &HashMap<String, Decimal>| {
+                let mut x: Vec<String> = x.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                x.sort();
+                x.join(",")
+            };
+            let change = global
+                .market_cap_change_percentage_24h_usd
+                .map_or("-".to_string(), |x| x.to_string());
+            println!("active_cryptocurrencies\t{}", global.active_cryptocurrencies);
+            println!("markets\t{}", global.markets);
+            println!("total_market_cap\t{}", sorted(&global.total_market_cap));
+            println!("total_volume\t{}", sorted(&global.total_volume));
+            println!("market_cap_percentage\t{}", sorted(&global.market_cap_percentage));
+            println!("market_cap_change_percentage_24h_usd\t{}", change);
+            if let Some(updated_at) = global.updated_at {
+                println!("updated_at\t{}", updated_at.to_rfc3339());
+            }
+        }
+        OutputFormat::Json => print_json(global),
+    }
+}
+
+pub fn print_trending(trending: &CgTrending, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for x in &trending.coins {
+                let coin = &x.item;
+                let rank = coin.market_cap_rank.map_or("-".to_string(), |x| x.to_string());
+                let price_btc = coin.price_btc.map_or("-".to_string(), |x| x.to_string());
+                println!("coin\t{}\t{}\t{}\t{}", coin.id, coin.symbol, rank, price_btc);
+            }
+            for nft in &trending.nfts {
+                println!("nft\t{}\t{}\t{}", nft.id, nft.symbol, nft.name);
+            }
+            for category in &trending.categories {
+                println!("category\t{}", category.name);
+            }
+        }
+        OutputFormat::Json => print_json(trending),
+    }
+}
+
+/// Text is `id symbol match market_cap name`, `/search` hits show `#rank` for the market cap.
+pub fn print_search_hits(hits: &[SearchHit], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for hit in hits {
+                let market_cap = match (hit.market_cap, hit.market_cap_rank) {
+                    (Some(x), _) => x.to_string(),
+                    (None, Some(x)) => format!("#{}", x),
+                    (None, None) => "-".to_string(),
+                };
+                let matched = format!("{:?}", hit.matched).to_lowercase();
+                println!("{}\t{}\t{}\t{}\t{}", hit.id, hit.symbol, matched, market_cap, hit.name);
+            }
+        }
+        OutputFormat::Json => print_json(&hits),
+    }
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::time::Duration;
+use toml::{Table, Value};
+use url::Url;
+use crate::filter::CoinFilterConfig;
+use crate::numeric::NumericConfig;
+use crate::scheduler::{ProbePolicy, Tier, TierRule};
+
+const DEFAULT_CONFIG_PATH: &str = "cg_req.toml";
+const ENV_PREFIX: &str = "CG_REQ_";
+const ENV_SEPARATOR: &str = "__";
+/// Upper bound of every period in the config, 10 years.
+const MAX_PERIOD_SEC: f32 = 315_360_000_f32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Can't read config {0}: {1}")]
+    Read(PathBuf, String),
+    #[error("{key}: {message}")]
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid(key: &str, message: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            key: key.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub client: ClientConfig,
+    pub scheduler: SchedulerConfig,
+    pub rates: RatesConfig,
+    pub numeric: NumericConfig,
+    pub metadata: MetadataConfig,
+    pub search: SearchConfig,
+    pub filter: CoinFilterConfig,
+    pub sinks: SinksConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub api_plan: ApiPlan,
+    pub sleep_between_requests_initial_ms: u64,
+    pub sleep_between_requests_step_ms: u64,
+    pub rates_tokens_per_request: usize,
+    /// Contract addresses per `/simple/token_price` request, the limit depends on the plan.
+    pub token_prices_addresses_per_request: usize,
+    /// On-disk HTTP cache, in-memory when not set.
+    pub cache_dir: Option<PathBuf>,
+    /// Cached responses kept, the least recently used ones go first. Permanent ones
+    /// come on top.
+    pub cache_max_entries: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.coingecko.com".to_string(),
+            api_key: None,
+            api_plan: ApiPlan::Demo,
+            sleep_between_requests_initial_ms: 10000,
+            sleep_between_requests_step_ms: 500,
+            rates_tokens_per_request: 500,
+            token_prices_addresses_per_request: 100,
+            cache_dir: None,
+            cache_max_entries: 200,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiPlan {
+    Demo,
+    Pro,
+}
+
+impl ApiPlan {
+    /// Query param carrying the API key.
+    pub fn key_param(&self) -> &'static str {
+        match self {
+            ApiPlan::Demo => "x_cg_demo_api_key",
+            ApiPlan::Pro => "x_cg_pro_api_key",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    pub coins_refresh_sec: f32,
+    pub watchlist: Vec<String>,
+    pub tiers: Vec<TierConfig>,
+    pub probe: ProbeConfig,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            coins_refresh_sec: 3600_f32,
+            watchlist: vec![],
+            tiers: vec![
+                TierConfig::new("watchlist", TierRuleConfig::Watchlist, 30_f32),
+                TierConfig::new(
+                    "top",
+                    TierRuleConfig::MinMarketCap(Decimal::from(100_000_000)),
+                    60_f32,
+                ),
+                TierConfig::new("active", TierRuleConfig::PriceSeenWithinSec(86400_f32), 300_f32),
+                TierConfig::new("long_tail", TierRuleConfig::All, 3600_f32),
+            ],
+            probe: ProbeConfig::default(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    pub fn tiers(&self) -> Vec<Tier> {
+        self.tiers
+            .iter()
+            .map(|x| {
+                let rule = match &x.rule {
+                    TierRuleConfig::Watchlist => TierRule::Watchlist,
+                    TierRuleConfig::MinMarketCap(min) => TierRule::MinMarketCap(*min),
+                    TierRuleConfig::PriceSeenWithinSec(sec) => {
+                        TierRule::PriceSeenWithin(Duration::from_secs_f32(*sec))
+                    }
+                    TierRuleConfig::All => TierRule::All,
+                };
+                Tier::new(&x.name, rule, Duration::from_secs_f32(x.interval_sec))
+            })
+            .collect()
+    }
+    pub fn watchlist(&self) -> HashSet<String> {
+        self.watchlist.iter().cloned().collect()
+    }
+    pub fn probe(&self) -> ProbePolicy {
+        ProbePolicy {
+            after_misses: self.probe.after_misses,
+            initial_backoff: Duration::from_secs_f32(self.probe.initial_backoff_sec),
+            max_backoff: Duration::from_secs_f32(self.probe.max_backoff_sec),
+        }
+    }
+}
+
+/// Backoff of coins that keep returning no price, see `ProbePolicy`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    pub after_misses: u32,
+    pub initial_backoff_sec: f32,
+    pub max_backoff_sec: f32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            after_misses: 3,
+            initial_backoff_sec: 3600_f32,
+            max_backoff_sec: 604800_f32,
+        }
+    }
+}
+
+/// `rule = "watchlist"`, `rule = { min_market_cap = 100000000 }`, ...
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TierConfig {
+    pub name: String,
+    pub rule: TierRuleConfig,
+    pub interval_sec: f32,
+}
+
+impl TierConfig {
+    fn new(name: &str, rule: TierRuleConfig, interval_sec: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            rule,
+            interval_sec,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TierRuleConfig {
+    Watchlist,
+    MinMarketCap(Decimal),
+    PriceSeenWithinSec(f32),
+    All,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatesConfig {
+    /// Quote currencies, checked against `/simple/supported_vs_currencies` on start.
+    pub vs_currencies: Vec<String>,
+    /// Rates CoinGecko hasn't updated for longer are stale, never when not set.
+    pub stale_after_hours: Option<f32>,
+    pub stale_policy: StalePolicy,
+}
+
+impl Default for RatesConfig {
+    fn default() -> Self {
+        Self {
+            vs_currencies: vec!["usd".to_string()],
+            stale_after_hours: None,
+            stale_policy: StalePolicy::Mark,
+        }
+    }
+}
+
+impl RatesConfig {
+    /// `None` also for values `validate` rejects.
+    pub fn stale_after(&self) -> Option<chrono::Duration> {
+        self.stale_after_hours
+            .and_then(|x| chrono::Duration::try_seconds((x * 3600_f32) as i64))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StalePolicy {
+    /// Keep stale rates, flag the coin as `stale`.
+    Mark,
+    /// Forget the coin's rates until a fresh one comes.
+    Drop,
+}
+
+/// Enrichment of coins from `/coins/{id}`, see `CoinRegistry`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// Coins to enrich, `scheduler.watchlist` when empty.
+    pub ids: Vec<String>,
+    pub ttl_sec: f32,
+    /// `/coins/{id}` requests per sync cycle, fewer when rates fall due first. 0 disables
+    /// enrichment.
+    pub per_cycle: usize,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            ids: vec![],
+            ttl_sec: 86400_f32,
+            per_cycle: 10,
+        }
+    }
+}
+
+impl MetadataConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs_f32(self.ttl_sec)
+    }
+}
+
+/// The `search` command, see `SearchIndex`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Best local hits whose USD market caps are fetched for ranking, 0 ranks without them.
+    pub ranked_hits: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self { ranked_hits: 250 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Print a few example coins after every rates pass.
+    pub log: bool,
+    /// Write the full coin snapshot as JSON after every rates pass.
+    pub json_file: Option<PathBuf>,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            log: true,
+            json_file: None,
+        }
+    }
+}
+
+/// Where the config comes from, kept to load it again on reload.
+#[derive(Clone)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    pub overrides: Vec<String>,
+}
+
+impl ConfigSource {
+    /// The file is `path`, else `CG_REQ_CONFIG`, else `cg_req.toml` when it exists.
+    pub fn new(path: Option<&Path>, overrides: &[String]) -> Self {
+        let path = match path {
+            Some(x) => Some(x.to_path_buf()),
+            None => match std::env::var("CG_REQ_CONFIG") {
+                Ok(x) if !x.is_empty() => Some(PathBuf::from(x)),
+                _ => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|x| x.exists()),
+            },
+        };
+        Self {
+            path,
+            overrides: overrides.to_vec(),
+        }
+    }
+
+    /// Layers, later wins: TOML file, `CG_REQ_<SECTION>__<KEY>` env vars, `--set key=value` flags.
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut table = match &self.path {
+            Some(path) => read_table(path)?,
+            None => Table::new(),
+        };
+        for (key, value) in std::env::vars() {
+            let Some(key) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if !key.contains(ENV_SEPARATOR) {
+                continue;
+            }
+            let key = key.to_lowercase().replace(ENV_SEPARATOR, ".");
+            set_value(&mut table, &key, &value)?;
+        }
+        for x in &self.overrides {
+            let Some((key, value)) = x.split_once('=') else {
+                return Err(ConfigError::invalid(x, "expected key=value"));
+            };
+            set_value(&mut table, key.trim(), value.trim())?;
+        }
+        let config: Config = serde_path_to_error::deserialize(Value::Table(table))
+            .map_err(|e| ConfigError::invalid(&e.path().to_string(), e.inner().message()))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let client = &self.client;
+        match Url::parse(&client.base_url) {
+            Ok(x) if x.scheme() != "https" => {
+                return Err(ConfigError::invalid("client.base_url", "must be https"));
+            }
+            Ok(_) => {}
+            Err(e) => return Err(ConfigError::invalid("client.base_url", e.to_string())),
+        }
+        if client.api_key.as_ref().is_some_and(|x| x.trim().is_empty()) {
+            return Err(ConfigError::invalid("client.api_key", "must not be empty"));
+        }
+        if client.rates_tokens_per_request == 0 {
+            return Err(ConfigError::invalid("client.rates_tokens_per_request", "must be positive"));
+        }
+        if client.token_prices_addresses_per_request == 0 {
+            let key = "client.token_prices_addresses_per_request";
+            return Err(ConfigError::invalid(key, "must be positive"));
+        }
+        if client.cache_max_entries == 0 {
+            return Err(ConfigError::invalid("client.cache_max_entries", "must be positive"));
+        }
+        if self.rates.vs_currencies.is_empty() {
+            return Err(ConfigError::invalid("rates.vs_currencies", "at least one is required"));
+        }
+        for (i, vs_currency) in self.rates.vs_currencies.iter().enumerate() {
+            if vs_currency.is_empty() || *vs_currency != vs_currency.to_lowercase() {
+                let key = format!("rates.vs_currencies[{}]", i);
+                return Err(ConfigError::invalid(&key, "must be a non-empty lowercase code"));
+            }
+        }
+        if let Some(hours) = self.rates.stale_after_hours {
+            let max = MAX_PERIOD_SEC / 3600_f32;
+            if !hours.is_finite() || hours <= 0_f32 || hours > max {
+                let message = format!("must be a positive number of at most {} hours", max);
+                return Err(ConfigError::invalid("rates.stale_after_hours", message));
+            }
+        }
+        if self.numeric.max_scale > 28 {
+            return Err(ConfigError::invalid("numeric.max_scale", "must be at most 28"));
+        }
+        check_period("scheduler.coins_refresh_sec", self.scheduler.coins_refresh_sec)?;
+        if self.scheduler.tiers.is_empty() {
+            return Err(ConfigError::invalid("scheduler.tiers", "at least one tier is required"));
+        }
+        let mut names = HashSet::new();
+        for (i, tier) in self.scheduler.tiers.iter().enumerate() {
+            if !names.insert(&tier.name) {
+                let message = format!("duplicate tier name '{}'", tier.name);
+                return Err(ConfigError::invalid(&format!("scheduler.tiers[{}].name", i), message));
+            }
+            check_period(&format!("scheduler.tiers[{}].interval_sec", i), tier.interval_sec)?;
+            if let TierRuleConfig::PriceSeenWithinSec(sec) = tier.rule {
+                let key = format!("scheduler.tiers[{}].rule.price_seen_within_sec", i);
+                check_period(&key, sec)?;
+            }
+        }
+        let probe = &self.scheduler.probe;
+        check_period("scheduler.probe.initial_backoff_sec", probe.initial_backoff_sec)?;
+        check_period("scheduler.probe.max_backoff_sec", probe.max_backoff_sec)?;
+        if probe.max_backoff_sec < probe.initial_backoff_sec {
+            let message = "must not be less than initial_backoff_sec";
+            return Err(ConfigError::invalid("scheduler.probe.max_backoff_sec", message));
+        }
+        check_period("metadata.ttl_sec", self.metadata.ttl_sec)?;
+        let regexes = [
+            ("filter.include_name_regex", &self.filter.include_name_regex),
+            ("filter.exclude_name_regex", &self.filter.exclude_name_regex),
+        ];
+        for (key, regex) in regexes {
+            if let Some(Err(e)) = regex.as_ref().map(|x| Regex::new(x)) {
+                return Err(ConfigError::invalid(key, e.to_string()));
+            }
+        }
+        if let Some(path) = &self.sinks.json_file {
+            if path.is_dir() {
+                return Err(ConfigError::invalid("sinks.json_file", "is a directory"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Periods end up in `Duration::from_secs_f32`, which panics on NaN, negative and huge values.
+fn check_period(key: &str, sec: f32) -> Result<(), ConfigError> {
+    if !sec.is_finite() || sec <= 0_f32 {
+        return Err(ConfigError::invalid(key, "must be a positive number"));
+    }
+    if sec > MAX_PERIOD_SEC {
+        return Err(ConfigError::invalid(key, format!("must be at most {} seconds", MAX_PERIOD_SEC)));
+    }
+    Ok(())
+}
+
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Read(path.to_path_buf(), e.to_string()))?;
+    content
+        .parse::<Table>()
+        .map_err(|e| ConfigError::Read(path.to_path_buf(), e.to_string()))
+}
+
+/// Sets a dotted `key` to `raw` parsed as a TOML value, bare strings are accepted as is.
+fn set_value(table: &mut Table, key: &str, raw: &str) -> Result<(), ConfigError> {
+    let value = match format!("v = {}", raw).parse::<Table>() {
+        Ok(mut x) => x.remove("v").unwrap(),
+        Err(_) => Value::String(raw.to_string()),
+    };
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|x| !x.is_empty());
+    let Some(last) = last else {
+        return Err(ConfigError::invalid(key, "empty key"));
+    };
+    let mut current = table;
+    for part in parts {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        current = match entry {
+            Value::Table(x) => x,
+            _ => return Err(ConfigError::invalid(key, format!("'{}' is not a table", part))),
+        };
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
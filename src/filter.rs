@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use regex::Regex;
+use serde::Deserialize;
+use crate::CgCoin;
+
+/// Which coins are synced. Explicit `include_ids` are always kept (unless excluded),
+/// other coins must match every configured `include_*` criterion. Empty config keeps all.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoinFilterConfig {
+    pub include_ids: Vec<String>,
+    pub exclude_ids: Vec<String>,
+    pub include_symbols: Vec<String>,
+    pub exclude_symbols: Vec<String>,
+    pub include_name_regex: Option<String>,
+    pub exclude_name_regex: Option<String>,
+    /// Platform keys of `CoingeckoTokenPlatforms`, e.g. `ethereum`.
+    pub include_platforms: Vec<String>,
+    pub exclude_platforms: Vec<String>,
+    /// Require (or forbid) a non-empty contract address on any platform.
+    pub has_contract_address: Option<bool>,
+}
+
+pub struct CoinFilter {
+    include_ids: HashSet<String>,
+    exclude_ids: HashSet<String>,
+    include_symbols: HashSet<String>,
+    exclude_symbols: HashSet<String>,
+    include_name_regex: Option<Regex>,
+    exclude_name_regex: Option<Regex>,
+    include_platforms: HashSet<String>,
+    exclude_platforms: HashSet<String>,
+    has_contract_address: Option<bool>,
+}
+
+impl CoinFilter {
+    pub fn new(config: &CoinFilterConfig) -> Result<Self, String> {
+        let regex = |x: &Option<String>| -> Result<Option<Regex>, String> {
+            match x {
+                Some(x) => Regex::new(x)
+                    .map(Some)
+                    .map_err(|e| format!("Invalid name regex '{}': {}", x, e)),
+                None => Ok(None),
+            }
+        };
+        let lowercase = |x: &Vec<String>| x.iter().map(|x| x.to_lowercase()).collect();
+        Ok(Self {
+            include_ids: config.include_ids.iter().cloned().collect(),
+            exclude_ids: config.exclude_ids.iter().cloned().collect(),
+            include_symbols: lowercase(&config.include_symbols),
+            exclude_symbols: lowercase(&config.exclude_symbols),
+            include_name_regex: regex(&config.include_name_regex)?,
+            exclude_name_regex: regex(&config.exclude_name_regex)?,
+            include_platforms: config.include_platforms.iter().cloned().collect(),
+            exclude_platforms: config.exclude_platforms.iter().cloned().collect(),
+            has_contract_address: config.has_contract_address,
+        })
+    }
+
+    pub fn matches(&self, coin: &CgCoin) -> bool {
+        if self.is_excluded(coin) {
+            return false;
+        }
+        if self.include_ids.contains(&coin.id) {
+            return true;
+        }
+        let has_criteria = !self.include_symbols.is_empty()
+            || self.include_name_regex.is_some()
+            || !self.include_platforms.is_empty()
+            || self.has_contract_address.is_some();
+        if !has_criteria {
+            // Only an explicit id list is configured.
+            return self.include_ids.is_empty();
+        }
+        if !self.include_symbols.is_empty()
+            && !self.include_symbols.contains(&coin.symbol.to_lowercase())
+        {
+            return false;
+        }
+        if let Some(regex) = &self.include_name_regex {
+            if !regex.is_match(&coin.name) {
+                return false;
+            }
+        }
+        if !self.include_platforms.is_empty()
+            && !coin.platforms.keys().any(|x| self.include_platforms.contains(x))
+        {
+            return false;
+        }
+        if let Some(required) = self.has_contract_address {
+            if has_contract_address(coin) != required {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn apply(&self, coins: Vec<CgCoin>) -> Vec<CgCoin> {
+        coins.into_iter().filter(|x| self.matches(x)).collect()
+    }
+
+    fn is_excluded(&self, coin: &CgCoin) -> bool {
+        self.exclude_ids.contains(&coin.id)
+            || self.exclude_symbols.contains(&coin.symbol.to_lowercase())
+            || self
+                .exclude_name_regex
+                .as_ref()
+                .is_some_and(|x| x.is_match(&coin.name))
+            || coin.platforms.keys().any(|x| self.exclude_platforms.contains(x))
+    }
+}
+
+fn has_contract_address(coin: &CgCoin) -> bool {
+    coin.platforms
+        .values()
+        .any(|x| x.as_ref().is_some_and(|x| !x.trim().is_empty()))
+}
@@ -1,23 +1,63 @@
+mod address;
+mod cache;
+mod chart;
+mod cli;
+mod config;
+mod filter;
 mod hyper;
+mod numeric;
+mod paginate;
+mod platform;
+mod registry;
+mod reload;
+mod report;
 mod rest;
+mod scheduler;
+mod search;
+mod shutdown;
+mod sink;
+mod sync;
 
-use std::collections::HashMap;
-use ::hyper::{body, HeaderMap, header::HeaderValue};
+use std::collections::{HashMap, HashSet};
+use ::hyper::{body, HeaderMap, header::{HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH}};
 use bytes::Bytes;
-use tokio::time::{sleep, Duration};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
 use rust_decimal::Decimal;
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use crate::config::{ClientConfig, ConfigSource, RatesConfig, StalePolicy};
+use crate::reload::ConfigWatcher;
+use crate::address::{
+    address_kind, is_valid_checksum, normalize_address, to_checksum_address, AddressIndex, AddressKind,
+};
+use crate::cache::{create_response_cache, CachedResponse, ResponseCache};
+use clap::Parser;
+use crate::cli::{
+    print_candles, print_chart, print_coins, print_exchange_rates, print_global, print_history,
+    print_markets, print_metadata, print_search_hits, print_trending, print_platforms, print_rates, Cli, Command,
+    OutputFormat,
+};
+use crate::paginate::paginate;
+use crate::registry::CoinMetadata;
+use crate::search::{rank, CgSearch, MatchKind, SearchHit, SearchIndex};
+use crate::chart::{
+    chunk_range, to_candles, Candle, CgRawMarketChart, CgRawOhlc, Granularity, MarketChart, OhlcDays,
+    OHLC_DAYS,
+};
+use futures::StreamExt;
+use crate::platform::{coins_on_platform, PlatformRegistry};
+use crate::numeric::{
+    deserialize_lenient_decimal, deserialize_lenient_decimal_map, parse_number, ExactNumber, NumericConfig, NumericValue};
 use crate::hyper::{compile_uri, create_hyper, create_request_builder, HyperClient};
-use serde::Deserialize;
-use crate::rest::{MethodResponse, ResponseTransformerError, RestApiMethod, RestApiMethodBuilder, RestApiMethodParam, ValidateResponseError};
+use serde::{Deserialize, Serialize};
+use crate::shutdown::{
+    finish_within_deadline, listen_shutdown_signals, sleep_or_cancel, interrupted_exit_code, CANCELLED,
+};
+use crate::sync::SyncContext;
+use crate::rest::{CachePolicy, MethodResponse, ResponseTransformerError, RestApiMethod, RestApiMethodBuilder, RestApiMethodParam, RestApiMethodRouteParam, ValidateResponseError};
 use rust_decimal::prelude::ToPrimitive;
 use serde::de::DeserializeOwned;
-use log::{info, warn};
-
-const BASE_URL: &str = "https://api.coingecko.com";
-const SLEEP_BETWEEN_REQUESTS_INITIAL_MS: u64 = 10000;
-const SLEEP_BETWEEN_REQUESTS_STEP_MS: u64 = 500;
-const RATES_TOKENS_PER_REQUEST: usize = 500;
-const COOLDOWN_SEC: f32 = 60_f32;
+use log::{debug, info, warn};
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -26,26 +66,396 @@ pub enum Error {
     Runtime(String),
 }
 
+/// Pause before every batch request, grows by `step_ms` on every ban.
+pub struct RequestPacing {
+    pub sleep_between_requests_ms: u64,
+    pub step_ms: u64,
+}
+
+impl RequestPacing {
+    pub fn new(client: &ClientConfig) -> Self {
+        Self {
+            sleep_between_requests_ms: client.sleep_between_requests_initial_ms,
+            step_ms: client.sleep_between_requests_step_ms,
+        }
+    }
+}
+
 pub type CoingeckoTokenPlatforms = HashMap<String, Option<String>>;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[allow(dead_code)]
 pub struct CgCoin {
     id: String,
     symbol: String,
     name: String,
     platforms: CoingeckoTokenPlatforms,
-    rate: Option<Decimal>,
+    /// Non-zero prices keyed by vs currency.
+    #[serde(default)]
+    rates: HashMap<String, Decimal>,
+    /// Non-zero prices that don't fit `Decimal`, e.g. `1.2e-31`, keyed by vs currency.
+    #[serde(default)]
+    exact_rates: HashMap<String, ExactNumber>,
+    market_cap: Option<Decimal>,
+    /// When CoinGecko last updated `rates`, `None` when unknown.
+    rates_updated_at: Option<DateTime<Utc>>,
+    /// When we last fetched `rates`.
+    rates_fetched_at: Option<DateTime<Utc>>,
+    /// Outcome of the last rates fetch, `None` until the coin is requested.
+    rate_status: Option<RateStatus>,
+}
+
+/// Entry of `/asset_platforms`, `id` is the key used in `CgCoin.platforms`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CgAssetPlatform {
+    pub id: String,
+    /// EVM chain id, `None` for non-EVM chains.
+    pub chain_identifier: Option<u64>,
+    pub name: String,
+    pub shortname: Option<String>,
+    pub native_coin_id: Option<String>,
+}
+
+/// Entry of `/coins/markets`, quoted in the requested vs currency.
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct CgMarket {
+    id: String,
+    symbol: String,
+    name: String,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    current_price: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    market_cap: Option<Decimal>,
+    market_cap_rank: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    fully_diluted_valuation: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    total_volume: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    high_24h: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    low_24h: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_change_24h: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_change_percentage_1h_in_currency: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_change_percentage_24h_in_currency: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_change_percentage_7d_in_currency: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_change_percentage_30d_in_currency: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    market_cap_change_24h: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    market_cap_change_percentage_24h: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    circulating_supply: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    total_supply: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    max_supply: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    ath: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    ath_change_percentage: Option<Decimal>,
+    ath_date: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    atl: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    atl_change_percentage: Option<Decimal>,
+    atl_date: Option<DateTime<Utc>>,
+    last_updated: Option<DateTime<Utc>>,
 }
 
+/// `/coins/{id}/history` snapshot, `market_data` is `None` for days before the coin was listed.
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct CgCoinHistory {
+    id: String,
+    symbol: String,
+    name: String,
+    market_data: Option<CgHistoryMarketData>,
+}
+
+/// Values keyed by vs currency at 00:00 UTC of the requested day.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgHistoryMarketData {
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    current_price: HashMap<String, Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    market_cap: HashMap<String, Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    total_volume: HashMap<String, Decimal>,
+}
+
+/// `/coins/{id}` with tickers and market data off, see `CoinMetadata`.
 #[derive(Deserialize, Debug)]
+pub struct CgCoinDetails {
+    id: String,
+    #[serde(default)]
+    categories: Vec<Option<String>>,
+    /// Image URLs keyed by size: thumb, small, large.
+    #[serde(default)]
+    image: HashMap<String, Option<String>>,
+    #[serde(default)]
+    links: CgCoinLinks,
+    genesis_date: Option<String>,
+    #[serde(default)]
+    detail_platforms: HashMap<String, CgDetailPlatform>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CgCoinLinks {
+    #[serde(default)]
+    homepage: Vec<Option<String>>,
+    /// Block explorers.
+    #[serde(default)]
+    blockchain_site: Vec<Option<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CgDetailPlatform {
+    decimal_place: Option<u32>,
+}
+
+/// `/exchange_rates`, BTC against fiat, crypto and commodities keyed by unit id.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgExchangeRates {
+    rates: HashMap<String, CgExchangeRate>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgExchangeRate {
+    name: String,
+    unit: String,
+    /// Units per BTC.
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    value: Option<Decimal>,
+    /// `fiat`, `crypto` or `commodity`.
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CgGlobalResponse {
+    data: CgGlobal,
+}
+
+/// `/global` totals, maps are keyed by vs currency and `market_cap_percentage` by coin symbol.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgGlobal {
+    active_cryptocurrencies: u64,
+    markets: u64,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    total_market_cap: HashMap<String, Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    total_volume: HashMap<String, Decimal>,
+    /// Dominance, percent.
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal_map")]
+    market_cap_percentage: HashMap<String, Decimal>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    market_cap_change_percentage_24h_usd: Option<Decimal>,
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    updated_at: Option<DateTime<Utc>>,
+}
+
+/// `/search/trending`, most searched coins, NFTs and categories of the last 24 hours.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgTrending {
+    #[serde(default)]
+    coins: Vec<CgTrendingCoinItem>,
+    #[serde(default)]
+    nfts: Vec<CgTrendingNft>,
+    #[serde(default)]
+    categories: Vec<CgTrendingCategory>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgTrendingCoinItem {
+    item: CgTrendingCoin,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgTrendingCoin {
+    id: String,
+    symbol: String,
+    name: String,
+    market_cap_rank: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_lenient_decimal")]
+    price_btc: Option<Decimal>,
+    /// 0 is the most searched.
+    score: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgTrendingNft {
+    id: String,
+    symbol: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CgTrendingCategory {
+    name: String,
+    slug: Option<String>,
+}
+
+/// `order` of `/coins/markets`.
+#[derive(Clone, Copy, Debug, strum_macros::Display, clap::ValueEnum)]
+#[strum(serialize_all = "snake_case")]
+pub enum MarketsOrder {
+    MarketCapDesc,
+    MarketCapAsc,
+    VolumeDesc,
+    VolumeAsc,
+    IdDesc,
+    IdAsc,
+}
+
+/// What `/simple/price` said about a coin the last time it was requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateStatus {
+    /// At least one non-zero price, `Decimal` or exact.
+    Priced,
+    /// Every returned price is 0.
+    ZeroReported,
+    /// Returned without any price, or with `null` prices.
+    NullReported,
+    /// Requested but missing from the response. The last prices are kept,
+    /// `rates_updated_at` tells how old they are.
+    NotReturned,
+    /// Not updated by CoinGecko within `rates.stale_after_hours`.
+    Stale,
+}
+
+#[derive(Serialize, Debug)]
 #[allow(dead_code)]
 pub struct CgRate {
-    #[serde(default, with = "rust_decimal::serde::arbitrary_precision_option")]
-    usd: Option<Decimal>,
-    // #[serde(default, with = "rust_decimal::serde::arbitrary_precision_option")]
-    // usd_market_cap: Option<Decimal>, //@TODO Scale exceeds the maximum precision allowed: 35 > 28
-    last_updated_at: Option<u32>, // Important! Can be 0
+    /// Prices keyed by vs currency, `null` prices are left out.
+    prices: HashMap<String, Decimal>,
+    market_caps: HashMap<String, Decimal>,
+    volumes_24h: HashMap<String, Decimal>,
+    /// Percent.
+    changes_24h: HashMap<String, Decimal>,
+    /// Values that don't fit `Decimal`, keyed by the response field, see `numeric`.
+    exact: HashMap<String, ExactNumber>,
+    /// `None` when not returned or 0, which CoinGecko uses for unknown.
+    last_updated_at: Option<DateTime<Utc>>,
+    fetched_at: DateTime<Utc>,
+    /// Everything else the response carries.
+    other: HashMap<String, serde_json::Value>,
+}
+
+impl CgRate {
+    pub fn price(&self, vs_currency: &str) -> Option<Decimal> {
+        self.prices.get(vs_currency).copied()
+    }
+
+    /// Prices kept as `ExactNumber`, keyed by vs currency.
+    pub fn exact_prices(&self) -> impl Iterator<Item = (&String, &ExactNumber)> {
+        self.exact.iter().filter(|(key, _)| !key.contains('_'))
+    }
+
+    /// Status of the coin, not telling staleness. Exact prices are never zero, they count as priced.
+    pub fn status(&self) -> RateStatus {
+        if self.prices.values().any(|x| !x.is_zero()) || self.exact_prices().next().is_some() {
+            RateStatus::Priced
+        } else if !self.prices.is_empty() {
+            RateStatus::ZeroReported
+        } else {
+            RateStatus::NullReported
+        }
+    }
+
+    /// Not updated by CoinGecko for longer than `max_age` when fetched, unknown update time is never stale.
+    pub fn is_stale(&self, max_age: chrono::Duration) -> bool {
+        self.last_updated_at
+            .is_some_and(|x| self.fetched_at - x > max_age)
+    }
+
+    /// Decodes one coin of a `/simple/price` response.
+    /// Vs currency codes never contain `_`, so `usd_market_cap` can't be a price.
+    pub fn from_json(
+        value: serde_json::Value,
+        numeric: &NumericConfig,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<Self, String> {
+        let serde_json::Value::Object(fields) = value else {
+            return Err(format!("Expected an object, got {}", value));
+        };
+        let mut rate = CgRate {
+            prices: HashMap::new(),
+            market_caps: HashMap::new(),
+            volumes_24h: HashMap::new(),
+            changes_24h: HashMap::new(),
+            exact: HashMap::new(),
+            last_updated_at: None,
+            fetched_at,
+            other: HashMap::new(),
+        };
+        for (key, value) in fields {
+            if key == "last_updated_at" {
+                rate.last_updated_at = match value.as_i64() {
+                    Some(0) => None,
+                    Some(x) => match DateTime::from_timestamp(x, 0) {
+                        Some(x) => Some(x),
+                        None => return Err(format!("Invalid last_updated_at: {}", value)),
+                    },
+                    None if value.is_null() => None,
+                    None => return Err(format!("Invalid last_updated_at: {}", value)),
+                };
+                continue;
+            }
+            let (target, vs_currency) = if let Some(x) = key.strip_suffix("_market_cap") {
+                (&mut rate.market_caps, x)
+            } else if let Some(x) = key.strip_suffix("_24h_vol") {
+                (&mut rate.volumes_24h, x)
+            } else if let Some(x) = key.strip_suffix("_24h_change") {
+                (&mut rate.changes_24h, x)
+            } else if !key.contains('_') {
+                (&mut rate.prices, key.as_str())
+            } else {
+                rate.other.insert(key, value);
+                continue;
+            };
+            match &value {
+                serde_json::Value::Number(number) => {
+                    match parse_number(&number.to_string(), numeric) {
+                        Ok(NumericValue::Decimal(x)) => {
+                            target.insert(vs_currency.to_string(), x);
+                        }
+                        Ok(NumericValue::Exact(x)) => {
+                            rate.exact.insert(key, x);
+                        }
+                        Err(e) => return Err(format!("{}: {}", key, e)),
+                    }
+                }
+                serde_json::Value::Null => {}
+                _ => return Err(format!("Invalid {}: {}", key, value)),
+            }
+        }
+        Ok(rate)
+    }
+}
+
+/// `/simple/price` response before per-coin decoding.
+pub type CgRawRates = HashMap<String, serde_json::Value>;
+
+/// Coin whose `/simple/price` entry couldn't be decoded, the rest of the batch is kept.
+#[derive(Serialize, Debug)]
+pub struct RateFailure {
+    pub id: String,
+    pub raw: serde_json::Value,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct FetchedRates {
+    pub rates: CgRates,
+    pub failures: Vec<RateFailure>,
 }
 
 pub type CgRates = HashMap<String, CgRate>;
@@ -54,140 +464,358 @@ pub type CgRates = HashMap<String, CgRate>;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenvy::dotenv().ok();
     env_logger::init();
-    info!("Sync coingecko tokens.");
+    let cli = Cli::parse();
+    let config_source = ConfigSource::new(cli.config.as_deref(), &cli.set);
+    let config = match config_source.load() {
+        Ok(x) => x,
+        Err(e) => return Err(get_execution_error(Error::Runtime(e.to_string()))),
+    };
     let hyper = create_hyper();
-    let all_coins_rest_api_method_template = build_all_coins_rest_api_method_builder_template();
-    let coin_rates_rest_api_method_template =
-        build_coin_rates_rest_api_method_builder_template();
-
-    let mut sleep_between_requests_ms = SLEEP_BETWEEN_REQUESTS_INITIAL_MS;
-    loop {
-        let mut coins = match fetch_coins(
+    let mut cache = match create_response_cache(
+        config.client.cache_dir.as_ref(),
+        config.client.cache_max_entries,
+    ) {
+        Ok(x) => x,
+        Err(e) => return Err(get_execution_error(Error::Runtime(e))),
+    };
+    let cancel = CancellationToken::new();
+    listen_shutdown_signals(cancel.clone());
+    let mut ctx = match SyncContext::new(&hyper, cache.as_mut(), config, cancel) {
+        Ok(x) => x,
+        Err(e) => return Err(get_execution_error(Error::Runtime(e))),
+    };
+    let res = match cli.command.unwrap_or(Command::Sync) {
+        Command::Sync => {
+            info!("Sync coingecko tokens.");
+            let mut watcher = ConfigWatcher::new(config_source);
+            sync::run(&mut ctx, &mut watcher).await
+        }
+        Command::Once => match sync::run_once(&mut ctx).await {
+            Ok(state) => {
+                print_coins(&state.coins, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Coins => match fetch_coins(
             &hyper,
-            &all_coins_rest_api_method_template,
-            &mut sleep_between_requests_ms,
+            &ctx.all_coins_rest_api_method_template,
+            ctx.cache,
+            &mut ctx.pacing,
+            &ctx.cancel,
         )
             .await
         {
-            Ok(x) => x,
-            Err(e) => {
-                return Err(get_execution_error(Error::Runtime(format!(
-                    "fetch_coins error: {}",
-                    e
-                ))));
+            Ok(coins) => {
+                print_coins(&ctx.filter.apply(coins), cli.format);
+                Ok(())
             }
-        };
-        let coins_len = coins.len();
-        info!("Fetched {} tokens.", coins_len);
-
-        let iterations = coins_len.div_ceil(RATES_TOKENS_PER_REQUEST);
-
-        let mut rates_found_num = 0;
-        for i in 0..iterations {
-            let start = i * RATES_TOKENS_PER_REQUEST;
-            let end = if i == iterations - 1 {
-                coins_len
-            } else {
-                start + RATES_TOKENS_PER_REQUEST
-            };
-            // println!("i{} s{} e{}", i, start, end);
-            let ids: Vec<String> = coins[start..end].iter().map(|e| e.id.clone()).collect();
-            // println!("{:?}", ids);
-
-            info!(
-                    "Sleeping {}ms between requests...",
-                    sleep_between_requests_ms
-                );
-            sleep(Duration::from_millis(sleep_between_requests_ms)).await;
-            let rates = match fetch_rates(
+            Err(e) => Err(format!("fetch_coins error: {}", e)),
+        },
+        Command::Price { ids, vs } => {
+            let vs = vs.unwrap_or_else(|| ctx.config.rates.vs_currencies.clone());
+            match check_vs_currencies(
                 &hyper,
-                &coin_rates_rest_api_method_template,
-                ids,
-                &mut sleep_between_requests_ms,
+                &ctx.config.client,
+                &vs,
+                ctx.cache,
+                &mut ctx.pacing,
+                &ctx.cancel,
             )
                 .await
             {
-                Ok(x) => x,
-                Err(e) => {
-                    return Err(get_execution_error(Error::Runtime(format!(
-                        "fetch_rates error: {}",
-                        e
-                    ))));
-                }
+                Ok(()) => match fetch_rates(
+                    &hyper,
+                    &build_coin_rates_rest_api_method_builder_template(&ctx.config.client, &vs),
+                    ids,
+                    ctx.cache,
+                    &ctx.config.numeric,
+                    &mut ctx.pacing,
+                    &ctx.cancel,
+                )
+                    .await
+                {
+                    Ok(fetched) => {
+                        print_rates(&checked_rates(fetched, &ctx.config.rates), cli.format);
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("fetch_rates error: {}", e)),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        Command::TokenPrice { platform, addresses, vs } => {
+            let vs = vs.unwrap_or_else(|| ctx.config.rates.vs_currencies.clone());
+            let checked = match fetch_platform_registry(&mut ctx).await {
+                Ok(registry) => check_platform(&registry, &platform),
+                Err(e) => Err(e),
             };
-            rates_found_num += rates.len();
-            info!(
-                    "Fetched {} ({}) rates of {} tokens.",
-                    rates.len(),
-                    rates_found_num,
-                    coins_len
-                );
-            for (id, rate) in &rates {
-                if rate.usd.is_some() {
-                    let rate_usd = if rate.usd.unwrap().is_zero() {
-                        None
-                    } else {
-                        rate.usd
-                    };
-                    if let Some(coin) = coins.iter_mut().find(|x| { x.id == id.as_str() }) {
-                        coin.rate = rate_usd;
+            let checked = match checked {
+                Ok(()) => check_vs_currencies(
+                    &hyper,
+                    &ctx.config.client,
+                    &vs,
+                    ctx.cache,
+                    &mut ctx.pacing,
+                    &ctx.cancel,
+                )
+                    .await,
+                Err(e) => Err(e),
+            };
+            match checked {
+                Ok(()) => match fetch_token_prices(
+                    &hyper,
+                    &ctx.config.client,
+                    &vs,
+                    &platform,
+                    &addresses,
+                    ctx.cache,
+                    &ctx.config.numeric,
+                    &mut ctx.pacing,
+                    &ctx.cancel,
+                )
+                    .await
+                {
+                    Ok(fetched) => {
+                        print_rates(&checked_rates(fetched, &ctx.config.rates), cli.format);
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("fetch_token_prices error: {}", e)),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        Command::Markets { vs, order, per_page, pages } => {
+            let vs = vs.unwrap_or_else(|| ctx.config.rates.vs_currencies[0].clone());
+            let template = build_coin_markets_rest_api_method_builder_template(
+                &ctx.config.client,
+                &vs,
+                order,
+                per_page,
+            );
+            let stream = paginate(
+                &hyper,
+                &template,
+                ctx.cache,
+                &mut ctx.pacing,
+                &ctx.cancel,
+                extract_markets,
+            )
+                .take(pages.unwrap_or(u32::MAX) as usize);
+            futures::pin_mut!(stream);
+            // Text goes out page by page, JSON has to be a single array.
+            let mut collected = vec![];
+            let mut res = Ok(());
+            while let Some(page) = stream.next().await {
+                match (page, cli.format) {
+                    (Ok(markets), OutputFormat::Text) => print_markets(&markets, cli.format),
+                    (Ok(markets), OutputFormat::Json) => collected.extend(markets),
+                    (Err(e), _) => {
+                        res = Err(e);
+                        break;
                     }
                 }
             }
+            if let OutputFormat::Json = cli.format {
+                print_markets(&collected, cli.format);
+            }
+            res
         }
-
-
-        println!("Example:");
-        println!("{:?}", coins[0]);
-        println!("{:?}", coins[10]);
-        println!("{:?}", coins[100]);
-        println!("{:?}", coins[1000]);
-        println!("{:?}", coins[10000]);
-
-        info!("Done, sleeping {} seconds to repeat...", COOLDOWN_SEC);
-        sleep(Duration::from_secs_f32(COOLDOWN_SEC)).await;
+        Command::Chart { id, vs, days, from, to, granularity } => {
+            let vs = vs.unwrap_or_else(|| ctx.config.rates.vs_currencies[0].clone());
+            let res = match (from, days) {
+                (Some(from), _) => {
+                    let to = to.unwrap_or_else(Utc::now);
+                    fetch_market_chart_range(&mut ctx, &id, &vs, from, to, granularity).await
+                }
+                (None, Some(days)) => fetch_market_chart(&mut ctx, &id, &vs, &days).await,
+                (None, None) => Err("Either --days or --from is required.".to_string()),
+            };
+            match res {
+                Ok(chart) => {
+                    print_chart(&chart, cli.format);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Command::ExchangeRates => match fetch_exchange_rates(&mut ctx).await {
+            Ok(rates) => {
+                print_exchange_rates(&rates, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Global => match fetch_global(&mut ctx).await {
+            Ok(global) => {
+                print_global(&global, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Trending => match fetch_trending(&mut ctx).await {
+            Ok(trending) => {
+                print_trending(&trending, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Search { query, limit } => match search(&mut ctx, &query, limit).await {
+            Ok(hits) => {
+                print_search_hits(&hits, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Info { id } => match fetch_coin_metadata(&mut ctx, &id).await {
+            Ok(metadata) => {
+                print_metadata(&metadata, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Ohlc { id, vs, days } => {
+            let vs = vs.unwrap_or_else(|| ctx.config.rates.vs_currencies[0].clone());
+            match fetch_ohlc(&mut ctx, &id, &vs, days).await {
+                Ok(candles) => {
+                    print_candles(&candles, cli.format);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Command::History { id, date } => match fetch_coin_history(&mut ctx, &id, date).await {
+            Ok(history) => {
+                print_history(&history, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Platforms => match fetch_asset_platforms(
+            &hyper,
+            &ctx.config.client,
+            ctx.cache,
+            &mut ctx.pacing,
+            &ctx.cancel,
+        )
+            .await
+        {
+            Ok(platforms) => {
+                print_platforms(&platforms, cli.format);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        },
+        Command::Lookup { platform, chain_id, address } => {
+            match lookup(&mut ctx, platform, chain_id, address).await {
+                Ok(coins) => {
+                    print_coins(&coins, cli.format);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        }
+    };
+    // Any command stopped by a signal exits the same way, `sync` after its final flush included.
+    match res {
+        Ok(()) if ctx.cancel.is_cancelled() => std::process::exit(interrupted_exit_code()),
+        Ok(()) => Ok(()),
+        Err(e) if ctx.cancel.is_cancelled() => {
+            warn!("Interrupted: {}", e);
+            std::process::exit(interrupted_exit_code());
+        }
+        Err(e) => Err(get_execution_error(Error::Runtime(e))),
     }
 }
 
-fn build_all_coins_rest_api_method_builder_template() -> RestApiMethodBuilder {
+fn build_all_coins_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
     let transform_response = |code: &u16,
                               body: &Bytes,
                               headers: &HeaderMap<HeaderValue>|
                               -> Result<MethodResponse, ResponseTransformerError> {
         match validate_response::<Vec<CgCoin>>(code, body, headers) {
             Ok(x) => Ok(MethodResponse::CgAllCoins(x)),
-            Err(e) => return Err(ResponseTransformerError::ValidateResponseError(e)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
         }
     };
     let mut builder = RestApiMethod::builder();
     builder
-        .set_base_url(BASE_URL.to_owned())
+        .set_base_url(client.base_url.clone())
         .set_url("/api/v3/coins/list")
         .add_param(RestApiMethodParam::prevalue(
             "include_platform",
             "true".to_string(),
         ))
         .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
     builder
 }
 
-fn build_coin_rates_rest_api_method_builder_template() -> RestApiMethodBuilder {
+fn build_coin_rates_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currencies: &[String],
+) -> RestApiMethodBuilder {
     let transform_response = |code: &u16,
                               body: &Bytes,
                               headers: &HeaderMap<HeaderValue>|
                               -> Result<MethodResponse, ResponseTransformerError> {
-        match validate_response::<CgRates>(code, body, headers) {
-            Ok(x) => Ok(MethodResponse::CgRates(x)),
-            Err(e) => return Err(ResponseTransformerError::ValidateResponseError(e)),
+        match validate_response::<CgRawRates>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgRawRates(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
         }
     };
     let mut builder = RestApiMethod::builder();
     builder
-        .set_base_url(BASE_URL.to_owned())
+        .set_base_url(client.base_url.clone())
         .set_url("/api/v3/simple/price")
         .add_param(RestApiMethodParam::prevalue(
             "vs_currencies",
-            "usd".to_string(),
+            vs_currencies.join(","),
+        ))
+        .add_param(RestApiMethodParam::prevalue("precision", "18".to_string()))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_last_updated_at",
+            "true".to_string(),
+        ))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_market_cap",
+            "true".to_string(),
+        ))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_24hr_vol",
+            "true".to_string(),
+        ))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_24hr_change",
+            "true".to_string(),
+        ))
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_token_prices_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currencies: &[String],
+) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgRawRates>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgRawRates(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/simple/token_price/{id}")
+        .add_route_param(RestApiMethodRouteParam::required("id"))
+        .add_param(RestApiMethodParam::required("contract_addresses"))
+        .add_param(RestApiMethodParam::prevalue(
+            "vs_currencies",
+            vs_currencies.join(","),
         ))
         .add_param(RestApiMethodParam::prevalue("precision", "18".to_string()))
         .add_param(RestApiMethodParam::prevalue(
@@ -198,18 +826,319 @@ fn build_coin_rates_rest_api_method_builder_template() -> RestApiMethodBuilder {
             "include_market_cap",
             "true".to_string(),
         ))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_24hr_vol",
+            "true".to_string(),
+        ))
+        .add_param(RestApiMethodParam::prevalue(
+            "include_24hr_change",
+            "true".to_string(),
+        ))
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+/// `page` is left for `paginate`.
+fn build_coin_markets_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currency: &str,
+    order: MarketsOrder,
+    per_page: u32,
+) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<Vec<CgMarket>>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgMarkets(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/markets")
+        .add_param(RestApiMethodParam::prevalue("vs_currency", vs_currency.to_string()))
+        .add_param(RestApiMethodParam::prevalue("order", order.to_string()))
+        .add_param(RestApiMethodParam::prevalue("per_page", per_page.to_string()))
+        .add_param(RestApiMethodParam::required("page"))
+        .add_param(RestApiMethodParam::prevalue(
+            "price_change_percentage",
+            "1h,24h,7d,30d".to_string(),
+        ))
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn transform_market_chart_response(
+    code: &u16,
+    body: &Bytes,
+    headers: &HeaderMap<HeaderValue>,
+) -> Result<MethodResponse, ResponseTransformerError> {
+    match validate_response::<CgRawMarketChart>(code, body, headers) {
+        Ok(x) => Ok(MethodResponse::CgMarketChart(x)),
+        Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+    }
+}
+
+/// `id` and `days` (a number or `max`) are left for the caller.
+fn build_market_chart_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currency: &str,
+) -> RestApiMethodBuilder {
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/{id}/market_chart")
+        .add_route_param(RestApiMethodRouteParam::required("id"))
+        .add_param(RestApiMethodParam::prevalue("vs_currency", vs_currency.to_string()))
+        .add_param(RestApiMethodParam::required("days"))
+        .add_param(RestApiMethodParam::prevalue("precision", "18".to_string()))
+        .set_transform_response(transform_market_chart_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+/// `id`, `from` and `to` (UNIX seconds) are left for the caller.
+fn build_market_chart_range_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currency: &str,
+) -> RestApiMethodBuilder {
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/{id}/market_chart/range")
+        .add_route_param(RestApiMethodRouteParam::required("id"))
+        .add_param(RestApiMethodParam::prevalue("vs_currency", vs_currency.to_string()))
+        .add_param(RestApiMethodParam::required("from"))
+        .add_param(RestApiMethodParam::required("to"))
+        .add_param(RestApiMethodParam::prevalue("precision", "18".to_string()))
+        .set_transform_response(transform_market_chart_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+/// `id` and `date` are left for the caller.
+fn build_coin_history_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgCoinHistory>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgCoinHistory(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/{id}/history")
+        .add_route_param(RestApiMethodRouteParam::required("id"))
+        .add_param(RestApiMethodParam::required("date"))
+        .add_param(RestApiMethodParam::prevalue("localization", "false".to_string()))
         .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
     builder
 }
 
+/// `id` and `days` are left for the caller, `days` must be one of `OHLC_DAYS`.
+fn build_ohlc_rest_api_method_builder_template(
+    client: &ClientConfig,
+    vs_currency: &str,
+) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgRawOhlc>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgOhlc(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/{id}/ohlc")
+        .add_route_param(RestApiMethodRouteParam::required("id"))
+        .add_param(RestApiMethodParam::prevalue("vs_currency", vs_currency.to_string()))
+        .add_param(RestApiMethodParam::one_of("days", OHLC_DAYS))
+        .add_param(RestApiMethodParam::prevalue("precision", "18".to_string()))
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+/// `id` is left for the caller.
+fn build_coin_details_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgCoinDetails>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgCoinDetails(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/coins/{id}")
+        .add_route_param(RestApiMethodRouteParam::required("id"));
+    for key in [
+        "localization",
+        "tickers",
+        "market_data",
+        "community_data",
+        "developer_data",
+        "sparkline",
+    ] {
+        builder.add_param(RestApiMethodParam::prevalue(key, "false".to_string()));
+    }
+    builder.set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_exchange_rates_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgExchangeRates>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgExchangeRates(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/exchange_rates")
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_global_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgGlobalResponse>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgGlobal(x.data)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/global")
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_trending_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgTrending>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgTrending(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/search/trending")
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_search_rest_api_method_builder_template(client: &ClientConfig) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<CgSearch>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgSearch(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/search")
+        .add_param(RestApiMethodParam::required("query"))
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_supported_vs_currencies_rest_api_method_builder_template(
+    client: &ClientConfig,
+) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<Vec<String>>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgSupportedVsCurrencies(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/simple/supported_vs_currencies")
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn build_asset_platforms_rest_api_method_builder_template(
+    client: &ClientConfig,
+) -> RestApiMethodBuilder {
+    let transform_response = |code: &u16,
+                              body: &Bytes,
+                              headers: &HeaderMap<HeaderValue>|
+                              -> Result<MethodResponse, ResponseTransformerError> {
+        match validate_response::<Vec<CgAssetPlatform>>(code, body, headers) {
+            Ok(x) => Ok(MethodResponse::CgAssetPlatforms(x)),
+            Err(e) => Err(ResponseTransformerError::ValidateResponseError(e)),
+        }
+    };
+    let mut builder = RestApiMethod::builder();
+    builder
+        .set_base_url(client.base_url.clone())
+        .set_url("/api/v3/asset_platforms")
+        .set_transform_response(transform_response);
+    add_api_key_param(&mut builder, client);
+    builder
+}
+
+fn add_api_key_param(builder: &mut RestApiMethodBuilder, client: &ClientConfig) {
+    if let Some(api_key) = &client.api_key {
+        builder.add_param(RestApiMethodParam::prevalue(
+            client.api_plan.key_param(),
+            api_key.clone(),
+        ));
+    }
+}
+
 async fn fetch_coins(
     hyper: &HyperClient,
     all_coins_rest_api_method_template: &RestApiMethodBuilder,
-    sleep_between_requests_ms: &mut u64,
+    cache: &mut dyn ResponseCache,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
 ) -> Result<Vec<CgCoin>, String> {
     let all_coins_rest_api_method = all_coins_rest_api_method_template.clone().build();
     let all_coins_response =
-        match request(&hyper, all_coins_rest_api_method, sleep_between_requests_ms).await {
+        match request(hyper, all_coins_rest_api_method, cache, pacing, cancel).await {
             Ok(x) => x,
             Err(e) => {
                 return Err(format!("All coins request error: {}", e));
@@ -226,58 +1155,592 @@ async fn fetch_rates(
     hyper: &HyperClient,
     coin_rates_rest_api_method_template: &RestApiMethodBuilder,
     ids: Vec<String>,
-    sleep_between_requests_ms: &mut u64,
-) -> Result<CgRates, String> {
+    cache: &mut dyn ResponseCache,
+    numeric: &NumericConfig,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
+) -> Result<FetchedRates, String> {
     let mut coin_rates_rest_api_method_builder = coin_rates_rest_api_method_template.clone();
     coin_rates_rest_api_method_builder
         .add_param(RestApiMethodParam::prevalue("ids", ids.join(",")));
     let coin_rates_response = match request(
         hyper,
         coin_rates_rest_api_method_builder.build(),
-        sleep_between_requests_ms,
+        cache,
+        pacing,
+        cancel,
     )
         .await
     {
         Ok(x) => x,
         Err(e) => return Err(format!("All coins request error: {}", e)),
     };
-    if let MethodResponse::CgRates(rates) = coin_rates_response {
+    if let MethodResponse::CgRawRates(raw_rates) = coin_rates_response {
         // println!("Rates num: {}", rates.len());
-        // println!("{:?}", rates);
+        Ok(decode_rates(raw_rates, numeric))
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+/// Logs decode failures and applies `rates.stale_policy` to rates printed as is.
+fn checked_rates(fetched: FetchedRates, config: &RatesConfig) -> CgRates {
+    for x in &fetched.failures {
+        warn!("Can't decode rate of {}: {} in {}", x.id, x.error, x.raw);
+    }
+    let mut rates = fetched.rates;
+    if let Some(max_age) = config.stale_after() {
+        rates.retain(|id, rate| {
+            if !rate.is_stale(max_age) {
+                return true;
+            }
+            warn!("Rate of {} is stale, updated at {:?}", id, rate.last_updated_at);
+            config.stale_policy == StalePolicy::Mark
+        });
+    }
+    rates
+}
+
+/// Decodes every entry on its own, see `CgRate::from_json`.
+fn decode_rates(raw_rates: CgRawRates, numeric: &NumericConfig) -> FetchedRates {
+    let mut fetched = FetchedRates::default();
+    let fetched_at = Utc::now();
+    for (id, raw) in raw_rates {
+        match CgRate::from_json(raw.clone(), numeric, fetched_at) {
+            Ok(x) => {
+                fetched.rates.insert(id, x);
+            }
+            Err(error) => fetched.failures.push(RateFailure { id, raw, error }),
+        }
+    }
+    fetched
+}
+
+/// Prices of contract addresses on a platform, keyed by normalized address.
+/// Addresses go in batches of `client.token_prices_addresses_per_request`.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_token_prices(
+    hyper: &HyperClient,
+    client: &ClientConfig,
+    vs_currencies: &[String],
+    platform: &str,
+    addresses: &[String],
+    cache: &mut dyn ResponseCache,
+    numeric: &NumericConfig,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
+) -> Result<FetchedRates, String> {
+    let mut normalized: Vec<String> = vec![];
+    for address in addresses {
+        match normalize_address(platform, address) {
+            Some(x) if !normalized.contains(&x) => normalized.push(x),
+            Some(_) => {}
+            None => warn!("Skipping empty contract address."),
+        }
+    }
+    let template = build_token_prices_rest_api_method_builder_template(client, vs_currencies);
+    let mut fetched = FetchedRates::default();
+    for (i, chunk) in normalized.chunks(client.token_prices_addresses_per_request).enumerate() {
+        if i > 0 {
+            sleep_or_cancel(cancel, Duration::from_millis(pacing.sleep_between_requests_ms)).await?;
+        }
+        let mut rest_api_method = template.build();
+        rest_api_method.set_route_param_value("id", platform.to_string())?;
+        rest_api_method.set_param_value("contract_addresses", chunk.join(","))?;
+        let response = match request(hyper, rest_api_method, cache, pacing, cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Token prices request error: {}", e)),
+        };
+        let MethodResponse::CgRawRates(raw_rates) = response else {
+            return Err("Api method response doesn't content correct variant.".to_string());
+        };
+        let batch = decode_rates(raw_rates, numeric);
+        for (address, rate) in batch.rates {
+            let address = normalize_address(platform, &address).unwrap_or(address);
+            fetched.rates.insert(address, rate);
+        }
+        fetched.failures.extend(batch.failures);
+    }
+    Ok(fetched)
+}
+
+async fn fetch_supported_vs_currencies(
+    hyper: &HyperClient,
+    client: &ClientConfig,
+    cache: &mut dyn ResponseCache,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>, String> {
+    let rest_api_method = build_supported_vs_currencies_rest_api_method_builder_template(client).build();
+    let response = match request(hyper, rest_api_method, cache, pacing, cancel).await {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Supported vs currencies request error: {}", e)),
+    };
+    if let MethodResponse::CgSupportedVsCurrencies(currencies) = response {
+        Ok(currencies)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_asset_platforms(
+    hyper: &HyperClient,
+    client: &ClientConfig,
+    cache: &mut dyn ResponseCache,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
+) -> Result<Vec<CgAssetPlatform>, String> {
+    let rest_api_method = build_asset_platforms_rest_api_method_builder_template(client).build();
+    let response = match request(hyper, rest_api_method, cache, pacing, cancel).await {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Asset platforms request error: {}", e)),
+    };
+    if let MethodResponse::CgAssetPlatforms(platforms) = response {
+        Ok(platforms)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_platform_registry(ctx: &mut SyncContext<'_>) -> Result<PlatformRegistry, String> {
+    let platforms = fetch_asset_platforms(
+        ctx.hyper,
+        &ctx.config.client,
+        ctx.cache,
+        &mut ctx.pacing,
+        &ctx.cancel,
+    )
+        .await?;
+    Ok(PlatformRegistry::new(platforms))
+}
+
+/// Catches a mistyped `--platform` before it is sent as a route param.
+fn check_platform(registry: &PlatformRegistry, platform: &str) -> Result<(), String> {
+    match registry.get(platform) {
+        Some(_) => Ok(()),
+        None => Err(format!("Unknown platform {}, see the platforms command", platform)),
+    }
+}
+
+/// Coins on a platform, given by id or EVM chain id, optionally narrowed to a contract address.
+async fn lookup(
+    ctx: &mut SyncContext<'_>,
+    platform: Option<String>,
+    chain_id: Option<u64>,
+    address: Option<String>,
+) -> Result<Vec<CgCoin>, String> {
+    let registry = fetch_platform_registry(ctx).await?;
+    let platform = match (platform, chain_id) {
+        (Some(x), _) => {
+            check_platform(&registry, &x)?;
+            x
+        }
+        (None, Some(chain_id)) => match registry.by_chain_id(chain_id) {
+            Some(x) => x.id.clone(),
+            None => return Err(format!("No platform with chain id {}", chain_id)),
+        },
+        _ => return Err("Either a platform or a chain id is required.".to_string()),
+    };
+    let coins = fetch_coins(
+        ctx.hyper,
+        &ctx.all_coins_rest_api_method_template,
+        ctx.cache,
+        &mut ctx.pacing,
+        &ctx.cancel,
+    )
+        .await
+        .map_err(|e| format!("fetch_coins error: {}", e))?;
+    let Some(address) = address else {
+        let found = match chain_id {
+            Some(chain_id) => registry.coins_on_chain(&coins, chain_id),
+            None => coins_on_platform(&coins, &platform),
+        };
+        let ids: Vec<String> = found.into_iter().map(|x| x.id.clone()).collect();
+        return Ok(coins.into_iter().filter(|x| ids.contains(&x.id)).collect());
+    };
+    let address = address.trim();
+    if address_kind(&platform, address) == AddressKind::Evm && !is_valid_checksum(address) {
+        let expected = to_checksum_address(address);
+        warn!("{} fails the EIP-55 checksum, expected {}", address, expected);
+    }
+    let index = AddressIndex::new(&coins);
+    debug!("Indexed {} addresses, {} null or empty.", index.len(), index.empty_num);
+    let ids = index.get(&platform, address);
+    if ids.len() > 1 {
+        warn!("{} on {} is listed for {} coins.", address, platform, ids.len());
+    }
+    Ok(coins.into_iter().filter(|x| ids.contains(&x.id)).collect())
+}
+
+fn extract_markets(response: MethodResponse) -> Result<Vec<CgMarket>, String> {
+    if let MethodResponse::CgMarkets(markets) = response {
+        Ok(markets)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+/// Series of the last `days` (a number or `max`), at the granularity CoinGecko picks.
+async fn fetch_market_chart(
+    ctx: &mut SyncContext<'_>,
+    id: &str,
+    vs_currency: &str,
+    days: &str,
+) -> Result<MarketChart, String> {
+    let mut rest_api_method =
+        build_market_chart_rest_api_method_builder_template(&ctx.config.client, vs_currency).build();
+    rest_api_method.set_route_param_value("id", id.to_string())?;
+    rest_api_method.set_param_value("days", days.to_string())?;
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Market chart request error: {}", e)),
+        };
+    let MethodResponse::CgMarketChart(raw) = response else {
+        return Err("Api method response doesn't content correct variant.".to_string());
+    };
+    let mut chart = MarketChart::default();
+    chart.merge(raw, None, None, &ctx.config.numeric);
+    Ok(chart)
+}
+
+/// Series within `[from, to]`, requested in chunks short enough for `granularity`.
+async fn fetch_market_chart_range(
+    ctx: &mut SyncContext<'_>,
+    id: &str,
+    vs_currency: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    granularity: Granularity,
+) -> Result<MarketChart, String> {
+    if from >= to {
+        return Err(format!("Range start {} is not before its end {}", from, to));
+    }
+    if let Some(earliest) = granularity.earliest_from(Utc::now()) {
+        if from < earliest {
+            let earliest = earliest.to_rfc3339_opts(SecondsFormat::Secs, true);
+            let message = format!("{:?} needs a range starting after {}", granularity, earliest);
+            return Err(format!("{}, got {}", message, from.to_rfc3339()));
+        }
+    }
+    let template =
+        build_market_chart_range_rest_api_method_builder_template(&ctx.config.client, vs_currency);
+    let mut chart = MarketChart::default();
+    for (i, (chunk_from, chunk_to)) in chunk_range(from, to, granularity).into_iter().enumerate() {
+        if i > 0 {
+            let sleep = Duration::from_millis(ctx.pacing.sleep_between_requests_ms);
+            sleep_or_cancel(&ctx.cancel, sleep).await?;
+        }
+        let mut rest_api_method = template.build();
+        rest_api_method.set_route_param_value("id", id.to_string())?;
+        rest_api_method.set_param_value("from", chunk_from.timestamp().to_string())?;
+        rest_api_method.set_param_value("to", chunk_to.timestamp().to_string())?;
+        let response =
+            match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+                Ok(x) => x,
+                Err(e) => return Err(format!("Market chart range request error: {}", e)),
+            };
+        let MethodResponse::CgMarketChart(raw) = response else {
+            return Err("Api method response doesn't content correct variant.".to_string());
+        };
+        chart.merge(raw, Some(from), Some(to), &ctx.config.numeric);
+    }
+    Ok(chart)
+}
+
+/// Snapshot of a coin on a past day. Finished days never change, so they are cached for good,
+/// across runs with `client.cache_dir`.
+async fn fetch_coin_history(
+    ctx: &mut SyncContext<'_>,
+    id: &str,
+    date: NaiveDate,
+) -> Result<CgCoinHistory, String> {
+    let mut rest_api_method =
+        build_coin_history_rest_api_method_builder_template(&ctx.config.client).build();
+    rest_api_method.set_route_param_value("id", id.to_string())?;
+    rest_api_method.set_param_value("date", date.format("%d-%m-%Y").to_string())?;
+    if date < Utc::now().date_naive() {
+        rest_api_method.cache_policy = CachePolicy::Permanent;
+    }
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Coin history request error: {}", e)),
+        };
+    if let MethodResponse::CgCoinHistory(history) = response {
+        Ok(history)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_ohlc(
+    ctx: &mut SyncContext<'_>,
+    id: &str,
+    vs_currency: &str,
+    days: OhlcDays,
+) -> Result<Vec<Candle>, String> {
+    let mut rest_api_method =
+        build_ohlc_rest_api_method_builder_template(&ctx.config.client, vs_currency).build();
+    rest_api_method.set_route_param_value("id", id.to_string())?;
+    rest_api_method.set_param_value("days", days.to_string())?;
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("OHLC request error: {}", e)),
+        };
+    if let MethodResponse::CgOhlc(raw) = response {
+        Ok(to_candles(raw, &ctx.config.numeric))
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_coin_metadata(ctx: &mut SyncContext<'_>, id: &str) -> Result<CoinMetadata, String> {
+    let mut rest_api_method =
+        build_coin_details_rest_api_method_builder_template(&ctx.config.client).build();
+    rest_api_method.set_route_param_value("id", id.to_string())?;
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Coin details request error: {}", e)),
+        };
+    if let MethodResponse::CgCoinDetails(details) = response {
+        Ok(CoinMetadata::from(details))
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_exchange_rates(ctx: &mut SyncContext<'_>) -> Result<CgExchangeRates, String> {
+    let rest_api_method =
+        build_exchange_rates_rest_api_method_builder_template(&ctx.config.client).build();
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Exchange rates request error: {}", e)),
+        };
+    if let MethodResponse::CgExchangeRates(rates) = response {
         Ok(rates)
     } else {
         Err("Api method response doesn't content correct variant.".to_string())
     }
 }
 
+async fn fetch_global(ctx: &mut SyncContext<'_>) -> Result<CgGlobal, String> {
+    let rest_api_method = build_global_rest_api_method_builder_template(&ctx.config.client).build();
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Global request error: {}", e)),
+        };
+    if let MethodResponse::CgGlobal(global) = response {
+        Ok(global)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_trending(ctx: &mut SyncContext<'_>) -> Result<CgTrending, String> {
+    let rest_api_method = build_trending_rest_api_method_builder_template(&ctx.config.client).build();
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Trending request error: {}", e)),
+        };
+    if let MethodResponse::CgTrending(trending) = response {
+        Ok(trending)
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+async fn fetch_search(ctx: &mut SyncContext<'_>, query: &str) -> Result<Vec<SearchHit>, String> {
+    let mut rest_api_method =
+        build_search_rest_api_method_builder_template(&ctx.config.client).build();
+    rest_api_method.set_param_value("query", query.to_string())?;
+    let response =
+        match request(ctx.hyper, rest_api_method, ctx.cache, &mut ctx.pacing, &ctx.cancel).await {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Search request error: {}", e)),
+        };
+    if let MethodResponse::CgSearch(search) = response {
+        Ok(search.into_hits())
+    } else {
+        Err("Api method response doesn't content correct variant.".to_string())
+    }
+}
+
+/// Matches `query` against the filtered coin list, ranking the best `search.ranked_hits`
+/// hits by USD market cap. Unless `limit` hits match by symbol, name or prefix, `/search`
+/// is asked too and its coins missing from the local hits go last.
+async fn search(
+    ctx: &mut SyncContext<'_>,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchHit>, String> {
+    let coins = fetch_coins(
+        ctx.hyper,
+        &ctx.all_coins_rest_api_method_template,
+        ctx.cache,
+        &mut ctx.pacing,
+        &ctx.cancel,
+    )
+        .await
+        .map_err(|e| format!("fetch_coins error: {}", e))?;
+    let index = SearchIndex::new(&ctx.filter.apply(coins));
+    let mut hits = index.search(query);
+    debug!("{} of {} coins match '{}'.", hits.len(), index.len(), query);
+    let ids: Vec<String> = hits
+        .iter()
+        .take(ctx.config.search.ranked_hits)
+        .map(|x| x.id.clone())
+        .collect();
+    // Market caps only come in USD, whatever `rates.vs_currencies` is.
+    let template = build_coin_rates_rest_api_method_builder_template(
+        &ctx.config.client,
+        &["usd".to_string()],
+    );
+    for (i, chunk) in ids.chunks(ctx.config.client.rates_tokens_per_request).enumerate() {
+        if i > 0 {
+            let sleep = Duration::from_millis(ctx.pacing.sleep_between_requests_ms);
+            sleep_or_cancel(&ctx.cancel, sleep).await?;
+        }
+        let fetched = fetch_rates(
+            ctx.hyper,
+            &template,
+            chunk.to_vec(),
+            ctx.cache,
+            &ctx.config.numeric,
+            &mut ctx.pacing,
+            &ctx.cancel,
+        )
+            .await
+            .map_err(|e| format!("fetch_rates error: {}", e))?;
+        for hit in hits.iter_mut() {
+            if let Some(rate) = fetched.rates.get(&hit.id) {
+                hit.market_cap = rate.market_caps.get("usd").copied();
+            }
+        }
+    }
+    rank(&mut hits);
+    let close = hits.iter().filter(|x| x.matched != MatchKind::Fuzzy).count();
+    if close < limit {
+        info!("{} close local matches of '{}', asking /search.", close, query);
+        let local: HashSet<String> = hits.iter().map(|x| x.id.clone()).collect();
+        let remote = fetch_search(ctx, query).await?;
+        hits.extend(remote.into_iter().filter(|x| !local.contains(&x.id)));
+    }
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+/// Fails on the first vs currency CoinGecko doesn't quote.
+async fn check_vs_currencies(
+    hyper: &HyperClient,
+    client: &ClientConfig,
+    vs_currencies: &[String],
+    cache: &mut dyn ResponseCache,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
+) -> Result<(), String> {
+    let supported = fetch_supported_vs_currencies(hyper, client, cache, pacing, cancel).await?;
+    for vs_currency in vs_currencies {
+        if !supported.contains(vs_currency) {
+            return Err(format!("Unsupported vs currency '{}'.", vs_currency));
+        }
+    }
+    Ok(())
+}
+
 async fn request(
     hyper: &HyperClient,
     rest_api_method: RestApiMethod,
-    sleep_between_requests_ms: &mut u64,
+    cache: &mut dyn ResponseCache,
+    pacing: &mut RequestPacing,
+    cancel: &CancellationToken,
 ) -> Result<MethodResponse, String> {
     #[allow(while_true)]
     while true {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
         let uri = compile_uri(&rest_api_method)?;
         // println!("{}", uri);
-        let request_builder = create_request_builder().uri(uri);
-        let request = request_builder.body(String::new()).unwrap();
-        let res = hyper.request(request).await.unwrap();
-        let status_code = res.status().as_u16();
-        //@TODO should try aggregate
-        // https://docs.rs/serde_json/latest/serde_json/fn.from_reader.html
-        let headers = res.headers().clone();
-        // let body = hyper::body::to_bytes(res.body().);
-        let body = body::to_bytes(res.into_body()).await.unwrap();
+        let cached = cache.get(&uri);
+        let (status_code, body, headers, to_cache) = match cached {
+            Some(entry) if entry.is_fresh() => {
+                debug!("Cache hit {}", uri);
+                (200, entry.body, HeaderMap::new(), None)
+            }
+            cached => {
+                let mut request_builder = create_request_builder().uri(uri.as_str());
+                if let Some(entry) = &cached {
+                    if let Some(etag) = &entry.etag {
+                        request_builder = request_builder.header(IF_NONE_MATCH, etag.as_str());
+                    }
+                    if let Some(last_modified) = &entry.last_modified {
+                        request_builder =
+                            request_builder.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                    }
+                }
+                let request = request_builder.body(String::new()).unwrap();
+                let (status_code, headers, body) = finish_within_deadline(cancel, async {
+                    let res = hyper
+                        .request(request)
+                        .await
+                        .map_err(|e| format!("Request error {}: {}", uri, e))?;
+                    let status_code = res.status().as_u16();
+                    //@TODO should try aggregate
+                    // https://docs.rs/serde_json/latest/serde_json/fn.from_reader.html
+                    let headers = res.headers().clone();
+                    let body = body::to_bytes(res.into_body())
+                        .await
+                        .map_err(|e| format!("Response body error {}: {}", uri, e))?;
+                    Ok::<_, String>((status_code, headers, body))
+                })
+                    .await??;
+                match (status_code, cached) {
+                    (304, Some(mut entry)) => {
+                        debug!("Cache revalidated {}", uri);
+                        entry.revalidate(&headers);
+                        let body = entry.body.clone();
+                        cache.put(&uri, entry);
+                        (200, body, headers, None)
+                    }
+                    (304, None) => return Err(format!("Not Modified without cached entry: {}", uri)),
+                    (200, _) => {
+                        let to_cache = match rest_api_method.cache_policy {
+                            CachePolicy::Http => {
+                                CachedResponse::from_response(body.clone(), &headers)
+                            }
+                            CachePolicy::Permanent => {
+                                Some(CachedResponse::permanent(body.clone(), &headers))
+                            }
+                        };
+                        (status_code, body, headers, to_cache)
+                    }
+                    _ => (status_code, body, headers, None),
+                }
+            }
+        };
         match (rest_api_method.transform_response)(&status_code, &body, &headers) {
-            Ok(x) => return Ok(x),
+            Ok(x) => {
+                // Only bodies that passed validation are worth keeping.
+                if let Some(entry) = to_cache {
+                    cache.put(&uri, entry);
+                }
+                return Ok(x);
+            }
             Err(e) => match &e {
                 ResponseTransformerError::ValidateResponseError(t_e) => match t_e {
                     ValidateResponseError::Banned(banned_for) => {
                         if let Some(seconds) = banned_for {
-                            *sleep_between_requests_ms += SLEEP_BETWEEN_REQUESTS_STEP_MS;
+                            pacing.sleep_between_requests_ms += pacing.step_ms;
                             let seconds = seconds + 1;
                             warn!("Banned for {} seconds, sleeping...", seconds);
-                            sleep(Duration::from_secs_f32(seconds.to_f32().unwrap())).await;
+                            sleep_or_cancel(cancel, Duration::from_secs_f32(seconds.to_f32().unwrap()))
+                                .await?;
                             continue;
                         } else {
                             return Err(format!("Banned for unknown time: {}", e));
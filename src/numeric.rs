@@ -0,0 +1,331 @@
+//! How JSON numbers from CoinGecko become `Decimal`.
+//!
+//! `Decimal` holds at most 28 fractional digits and integers up to ~7.9e28, while
+//! `/simple/price?precision=18` returns meme-coin prices like `1.2e-31` and supplies or
+//! market caps with 35+ significant digits. Every number is parsed from its exact text:
+//!
+//! * fractional digits beyond `max_scale` (or beyond what fits) are rounded with `rounding`;
+//! * a non-zero value that rounds to zero is an underflow, handled by `underflow`;
+//! * an integer part that doesn't fit is an overflow, the value is kept as `ExactNumber`.
+//!
+//! `ExactNumber` is the exact JSON text by default and `BigDecimal` with the `big-decimal`
+//! feature. Anything that isn't a number fails only the coin it belongs to.
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+#[cfg(feature = "big-decimal")]
+pub type ExactNumber = bigdecimal::BigDecimal;
+#[cfg(not(feature = "big-decimal"))]
+pub type ExactNumber = String;
+
+const MAX_DECIMAL_SCALE: u32 = 28;
+/// Exponents past this are out of range for any `Decimal` either way.
+const MAX_EXPONENT: i32 = 64;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NumericConfig {
+    pub rounding: Rounding,
+    /// Fractional digits kept, at most 28.
+    pub max_scale: u32,
+    pub underflow: Underflow,
+}
+
+impl Default for NumericConfig {
+    fn default() -> Self {
+        Self {
+            rounding: Rounding::HalfEven,
+            max_scale: MAX_DECIMAL_SCALE,
+            underflow: Underflow::Exact,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    HalfEven,
+    HalfUp,
+    Down,
+    Up,
+}
+
+/// What to do with a non-zero value that rounds to zero.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Underflow {
+    /// Keep it as `ExactNumber`.
+    Exact,
+    /// Store zero.
+    Zero,
+    /// Fail the coin.
+    Reject,
+}
+
+#[derive(Debug)]
+pub enum NumericValue {
+    Decimal(Decimal),
+    /// Doesn't fit `Decimal` without losing the value.
+    Exact(ExactNumber),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NumericError {
+    #[error("Invalid number {0}")]
+    Invalid(String),
+    #[error("Number {0} rounds to zero")]
+    Underflow(String),
+}
+
+/// Parses the exact text of a JSON number according to `config`.
+pub fn parse_number(s: &str, config: &NumericConfig) -> Result<NumericValue, NumericError> {
+    let invalid = || NumericError::Invalid(s.to_string());
+    let (negative, int, frac) = split_digits(s).ok_or_else(invalid)?;
+    let max_scale = config.max_scale.min(MAX_DECIMAL_SCALE) as usize;
+    let mut scale = max_scale.min(frac.len());
+    let value = loop {
+        let (int_r, frac_r) = round_digits(&int, &frac, scale, config.rounding);
+        let text = format!("{}{}.{}", if negative { "-" } else { "" }, int_r, frac_r);
+        if let Ok(x) = Decimal::from_str_exact(text.trim_end_matches('.')) {
+            break Some(x);
+        }
+        if scale == 0 {
+            break None;
+        }
+        scale -= 1;
+    };
+    let Some(value) = value else {
+        return Ok(NumericValue::Exact(exact_number(s)?));
+    };
+    let is_nonzero = int.bytes().chain(frac.bytes()).any(|x| x != b'0');
+    if value.is_zero() && is_nonzero {
+        return match config.underflow {
+            Underflow::Exact => Ok(NumericValue::Exact(exact_number(s)?)),
+            Underflow::Zero => Ok(NumericValue::Decimal(Decimal::ZERO)),
+            Underflow::Reject => Err(NumericError::Underflow(s.to_string())),
+        };
+    }
+    Ok(NumericValue::Decimal(value.normalize()))
+}
+
+#[cfg(feature = "big-decimal")]
+fn exact_number(s: &str) -> Result<ExactNumber, NumericError> {
+    use std::str::FromStr;
+    ExactNumber::from_str(s).map_err(|_| NumericError::Invalid(s.to_string()))
+}
+
+#[cfg(not(feature = "big-decimal"))]
+fn exact_number(s: &str) -> Result<ExactNumber, NumericError> {
+    Ok(s.to_string())
+}
+
+/// Sign, integer and fractional digits with the exponent applied.
+fn split_digits(s: &str) -> Option<(bool, String, String)> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(x) => (true, x),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    if !int.bytes().chain(frac.bytes()).all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let digits = format!("{}{}", int, frac);
+    if digits.bytes().all(|x| x == b'0') {
+        return Some((negative, "0".to_string(), String::new()));
+    }
+    let exponent = exponent.clamp(-MAX_EXPONENT * 4, MAX_EXPONENT);
+    let point = int.len() as i32 + exponent;
+    let (int, frac) = if point <= 0 {
+        ("0".to_string(), format!("{}{}", "0".repeat(-point as usize), digits))
+    } else if point as usize >= digits.len() {
+        (format!("{}{}", digits, "0".repeat(point as usize - digits.len())), String::new())
+    } else {
+        let (int, frac) = digits.split_at(point as usize);
+        (int.to_string(), frac.to_string())
+    };
+    let int = int.trim_start_matches('0');
+    Some((negative, if int.is_empty() { "0" } else { int }.to_string(), frac))
+}
+
+/// Cuts `frac` to `scale` digits. Rounds the magnitude on the digit strings, so the
+/// decision sees every dropped digit.
+fn round_digits(int: &str, frac: &str, scale: usize, rounding: Rounding) -> (String, String) {
+    if frac.len() <= scale {
+        return (int.to_string(), frac.to_string());
+    }
+    let (kept, dropped) = frac.split_at(scale);
+    let first = dropped.as_bytes()[0] - b'0';
+    let rest_nonzero = dropped[1..].bytes().any(|x| x != b'0');
+    let last_kept = kept.bytes().last().or(int.bytes().last()).map_or(0, |x| x - b'0');
+    let increment = match rounding {
+        Rounding::HalfEven => first > 5 || (first == 5 && (rest_nonzero || last_kept % 2 == 1)),
+        Rounding::HalfUp => first >= 5,
+        Rounding::Down => false,
+        Rounding::Up => first > 0 || rest_nonzero,
+    };
+    if !increment {
+        return (int.to_string(), kept.to_string());
+    }
+    let mut digits: Vec<u8> = format!("{}{}", int, kept).into_bytes();
+    let mut i = digits.len();
+    loop {
+        if i == 0 {
+            digits.insert(0, b'1');
+            break;
+        }
+        i -= 1;
+        if digits[i] == b'9' {
+            digits[i] = b'0';
+        } else {
+            digits[i] += 1;
+            break;
+        }
+    }
+    let split = digits.len() - kept.len();
+    let digits = String::from_utf8(digits).unwrap();
+    (digits[..split].to_string(), digits[split..].to_string())
+}
+
+/// `deserialize_with` for optional `Decimal` fields of bulk responses: numbers are parsed
+/// with the default policy, `null` and values that don't fit become `None`.
+pub fn deserialize_lenient_decimal<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    let Some(serde_json::Value::Number(number)) = value else {
+        return Ok(None);
+    };
+    match parse_number(&number.to_string(), &NumericConfig::default()) {
+        Ok(NumericValue::Decimal(x)) => Ok(Some(x)),
+        Ok(NumericValue::Exact(_)) | Err(_) => Ok(None),
+    }
+}
+
+/// `deserialize_with` for `Decimal` maps keyed by vs currency, entries that are `null` or
+/// don't fit are left out.
+pub fn deserialize_lenient_decimal_map<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values = Option::<HashMap<String, serde_json::Value>>::deserialize(deserializer)?;
+    let mut res = HashMap::new();
+    for (key, value) in values.unwrap_or_default() {
+        let serde_json::Value::Number(number) = value else {
+            continue;
+        };
+        let parsed = parse_number(&number.to_string(), &NumericConfig::default());
+        if let Ok(NumericValue::Decimal(x)) = parsed {
+            res.insert(key, x);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn config(rounding: Rounding, underflow: Underflow) -> NumericConfig {
+        NumericConfig {
+            rounding,
+            max_scale: MAX_DECIMAL_SCALE,
+            underflow,
+        }
+    }
+
+    fn decimal(s: &str, config: &NumericConfig) -> Decimal {
+        match parse_number(s, config) {
+            Ok(NumericValue::Decimal(x)) => x,
+            x => panic!("{} parsed as {:?}", s, x),
+        }
+    }
+
+    fn exact(s: &str, config: &NumericConfig) -> ExactNumber {
+        match parse_number(s, config) {
+            Ok(NumericValue::Exact(x)) => x,
+            x => panic!("{} parsed as {:?}", s, x),
+        }
+    }
+
+    #[test]
+    fn half_even_ties() {
+        let integers = NumericConfig {
+            max_scale: 0,
+            ..NumericConfig::default()
+        };
+        assert_eq!(decimal("2.5", &integers), Decimal::from(2));
+        assert_eq!(decimal("3.5", &integers), Decimal::from(4));
+        assert_eq!(decimal("2.5000001", &integers), Decimal::from(3));
+        let zero = config(Rounding::HalfEven, Underflow::Zero);
+        assert_eq!(decimal("0.5e-28", &zero), Decimal::ZERO);
+        assert_eq!(decimal("1.5e-28", &zero), Decimal::from_scientific("2e-28").unwrap());
+        let half_up = config(Rounding::HalfUp, Underflow::Zero);
+        assert_eq!(decimal("0.5e-28", &half_up), Decimal::from_scientific("1e-28").unwrap());
+    }
+
+    #[test]
+    fn carry_across_decimal_point() {
+        let nines = "9".repeat(29);
+        let config = NumericConfig::default();
+        assert_eq!(decimal(&format!("0.{}", nines), &config), Decimal::ONE);
+        assert_eq!(decimal(&format!("9.{}", nines), &config), Decimal::from(10));
+        let down = NumericConfig {
+            rounding: Rounding::Down,
+            ..NumericConfig::default()
+        };
+        let expected = Decimal::from_str(&format!("0.{}", "9".repeat(28))).unwrap();
+        assert_eq!(decimal(&format!("0.{}", nines), &down), expected);
+    }
+
+    #[test]
+    fn integer_overflow() {
+        let config = NumericConfig::default();
+        let max = "79228162514264337593543950335";
+        assert_eq!(decimal(max, &config), Decimal::MAX);
+        let above = "79228162514264337593543950336";
+        assert_eq!(exact(above, &config), exact_number(above).unwrap());
+        assert_eq!(exact(&"9".repeat(29), &config), exact_number(&"9".repeat(29)).unwrap());
+        // The fraction shrinks to fit, the last resort rounds the integer up past the max.
+        let s = format!("{}.5", max);
+        assert_eq!(exact(&s, &config), exact_number(&s).unwrap());
+        assert_eq!(decimal(&format!("{}.4", max), &config), Decimal::MAX);
+    }
+
+    #[test]
+    fn underflow() {
+        let s = "1.2e-31";
+        let exact_config = config(Rounding::HalfEven, Underflow::Exact);
+        assert_eq!(exact(s, &exact_config), exact_number(s).unwrap());
+        assert_eq!(decimal(s, &config(Rounding::HalfEven, Underflow::Zero)), Decimal::ZERO);
+        assert!(matches!(
+            parse_number(s, &config(Rounding::HalfEven, Underflow::Reject)),
+            Err(NumericError::Underflow(_))
+        ));
+        let up = config(Rounding::Up, Underflow::Reject);
+        assert_eq!(decimal(s, &up), Decimal::from_scientific("1e-28").unwrap());
+    }
+
+    #[test]
+    fn negative_zero() {
+        let config = config(Rounding::HalfEven, Underflow::Reject);
+        for s in ["-0.0", "-0", "-0e-40"] {
+            let x = decimal(s, &config);
+            assert!(x.is_zero(), "{}", s);
+            assert_eq!(x.to_string(), "0", "{}", s);
+        }
+        assert_eq!(decimal("-1.5e-28", &config), Decimal::from_scientific("-2e-28").unwrap());
+    }
+}
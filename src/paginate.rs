@@ -0,0 +1,61 @@
+use futures::stream::{self, Stream};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use crate::cache::ResponseCache;
+use crate::hyper::HyperClient;
+use crate::rest::{MethodResponse, RestApiMethodBuilder};
+use crate::shutdown::sleep_or_cancel;
+use crate::{request, RequestPacing};
+
+struct Pages<'a> {
+    hyper: &'a HyperClient,
+    template: &'a RestApiMethodBuilder,
+    cache: &'a mut dyn ResponseCache,
+    pacing: &'a mut RequestPacing,
+    cancel: &'a CancellationToken,
+    page: u32,
+}
+
+/// Requests `template` with `page` = 1, 2, ... until a page comes back empty. Pages are
+/// paced like rate batches, the stream ends after the first error it yields.
+pub fn paginate<'a, T: 'a>(
+    hyper: &'a HyperClient,
+    template: &'a RestApiMethodBuilder,
+    cache: &'a mut dyn ResponseCache,
+    pacing: &'a mut RequestPacing,
+    cancel: &'a CancellationToken,
+    extract: fn(MethodResponse) -> Result<Vec<T>, String>,
+) -> impl Stream<Item = Result<Vec<T>, String>> + 'a {
+    let pages = Pages {
+        hyper,
+        template,
+        cache,
+        pacing,
+        cancel,
+        page: 1,
+    };
+    stream::unfold(Some(pages), move |pages| async move {
+        let mut pages = pages?;
+        if pages.page > 1 {
+            let sleep = Duration::from_millis(pages.pacing.sleep_between_requests_ms);
+            if let Err(e) = sleep_or_cancel(pages.cancel, sleep).await {
+                return Some((Err(e), None));
+            }
+        }
+        let mut rest_api_method = pages.template.build();
+        if let Err(e) = rest_api_method.set_param_value("page", pages.page.to_string()) {
+            return Some((Err(e), None));
+        }
+        let res = request(pages.hyper, rest_api_method, pages.cache, pages.pacing, pages.cancel)
+            .await
+            .and_then(extract);
+        match res {
+            Ok(items) if items.is_empty() => None,
+            Ok(items) => {
+                pages.page += 1;
+                Some((Ok(items), Some(pages)))
+            }
+            Err(e) => Some((Err(format!("Page {}: {}", pages.page, e)), None)),
+        }
+    })
+}
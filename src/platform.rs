@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use log::warn;
+use crate::{CgAssetPlatform, CgCoin};
+
+/// Asset platforms from `/asset_platforms`, the keys of `CgCoin.platforms`.
+#[derive(Debug, Default)]
+pub struct PlatformRegistry {
+    platforms: Vec<CgAssetPlatform>,
+    by_id: HashMap<String, usize>,
+    by_chain_id: HashMap<u64, usize>,
+}
+
+impl PlatformRegistry {
+    /// The first platform wins when several claim the same chain id.
+    pub fn new(platforms: Vec<CgAssetPlatform>) -> Self {
+        let mut by_id = HashMap::new();
+        let mut by_chain_id = HashMap::new();
+        for (i, platform) in platforms.iter().enumerate() {
+            by_id.insert(platform.id.clone(), i);
+            let Some(chain_id) = platform.chain_identifier else {
+                continue;
+            };
+            if let Some(first) = by_chain_id.get(&chain_id) {
+                let first: &CgAssetPlatform = &platforms[*first];
+                warn!("Chain id {} is claimed by {} and {}.", chain_id, first.id, platform.id);
+                continue;
+            }
+            by_chain_id.insert(chain_id, i);
+        }
+        Self {
+            platforms,
+            by_id,
+            by_chain_id,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&CgAssetPlatform> {
+        self.by_id.get(id).map(|x| &self.platforms[*x])
+    }
+
+    /// Platform of an EVM chain id, e.g. 137 is `polygon-pos`.
+    pub fn by_chain_id(&self, chain_id: u64) -> Option<&CgAssetPlatform> {
+        self.by_chain_id.get(&chain_id).map(|x| &self.platforms[*x])
+    }
+
+    /// Coins with a contract address on the chain.
+    pub fn coins_on_chain<'a>(&self, coins: &'a [CgCoin], chain_id: u64) -> Vec<&'a CgCoin> {
+        match self.by_chain_id(chain_id) {
+            Some(platform) => coins_on_platform(coins, &platform.id),
+            None => vec![],
+        }
+    }
+}
+
+/// Coins with a non-empty contract address on the platform.
+pub fn coins_on_platform<'a>(coins: &'a [CgCoin], platform: &str) -> Vec<&'a CgCoin> {
+    coins
+        .iter()
+        .filter(|coin| {
+            coin.platforms
+                .get(platform)
+                .is_some_and(|x| x.as_ref().is_some_and(|x| !x.trim().is_empty()))
+        })
+        .collect()
+}
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDate;
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+use crate::{CgCoin, CgCoinDetails};
+
+/// What `/coins/{id}` adds to `CgCoin`, empty strings and nulls left out.
+#[derive(Serialize, Debug, Clone)]
+pub struct CoinMetadata {
+    pub id: String,
+    pub categories: Vec<String>,
+    pub image: HashMap<String, String>,
+    pub homepage: Vec<String>,
+    pub explorers: Vec<String>,
+    pub genesis_date: Option<NaiveDate>,
+    /// Token decimals keyed by platform, `None` when CoinGecko doesn't know them.
+    pub decimals: HashMap<String, Option<u32>>,
+}
+
+impl From<CgCoinDetails> for CoinMetadata {
+    fn from(details: CgCoinDetails) -> Self {
+        let non_empty = |x: Vec<Option<String>>| -> Vec<String> {
+            x.into_iter().flatten().filter(|x| !x.trim().is_empty()).collect()
+        };
+        Self {
+            id: details.id,
+            categories: non_empty(details.categories),
+            image: details
+                .image
+                .into_iter()
+                .filter_map(|(size, url)| Some((size, url?)))
+                .collect(),
+            homepage: non_empty(details.links.homepage),
+            explorers: non_empty(details.links.blockchain_site),
+            genesis_date: details
+                .genesis_date
+                .and_then(|x| NaiveDate::parse_from_str(&x, "%Y-%m-%d").ok()),
+            decimals: details
+                .detail_platforms
+                .into_iter()
+                .filter(|(platform, _)| !platform.is_empty())
+                .map(|(platform, x)| (platform, x.decimal_place))
+                .collect(),
+        }
+    }
+}
+
+/// Metadata of coins by id, entries expire after `ttl`.
+pub struct CoinRegistry {
+    metadata: HashMap<String, (CoinMetadata, Instant)>,
+    ttl: Duration,
+}
+
+impl CoinRegistry {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            metadata: HashMap::new(),
+            ttl,
+        }
+    }
+
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /// Metadata fetched within the TTL.
+    pub fn get(&self, id: &str, now: Instant) -> Option<&CoinMetadata> {
+        match self.metadata.get(id) {
+            Some((metadata, fetched_at)) if now.saturating_duration_since(*fetched_at) < self.ttl => {
+                Some(metadata)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, metadata: CoinMetadata, now: Instant) {
+        self.metadata.insert(metadata.id.clone(), (metadata, now));
+    }
+
+    /// Ids of `wanted` without fresh metadata, never fetched ones first.
+    pub fn due_ids(&self, wanted: &[String], now: Instant) -> Vec<String> {
+        let mut due: Vec<(Option<Instant>, &String)> = wanted
+            .iter()
+            .filter(|id| self.get(id, now).is_none())
+            .map(|id| (self.metadata.get(id).map(|(_, x)| *x), id))
+            .collect();
+        due.sort_by_key(|(fetched_at, _)| *fetched_at);
+        due.into_iter().map(|(_, id)| id.clone()).collect()
+    }
+
+    /// Drops metadata of coins that are no longer listed.
+    pub fn retain(&mut self, coins: &[CgCoin]) {
+        let ids: HashSet<&String> = coins.iter().map(|x| &x.id).collect();
+        self.metadata.retain(|id, _| ids.contains(id));
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Notify;
+use log::{info, warn};
+use crate::config::{Config, ConfigError, ConfigSource};
+
+/// Tells the sync loop when to load the config again: the file mtime changed or SIGHUP came.
+pub struct ConfigWatcher {
+    source: ConfigSource,
+    modified: Option<SystemTime>,
+    requested: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl ConfigWatcher {
+    pub fn new(source: ConfigSource) -> Self {
+        let watcher = Self {
+            modified: modified(&source),
+            source,
+            requested: Arc::new(AtomicBool::new(false)),
+            notify: Arc::new(Notify::new()),
+        };
+        watcher.listen_sighup();
+        watcher
+    }
+
+    /// New config if a reload is due, `Err` keeps the current one.
+    pub fn poll(&mut self) -> Option<Result<Config, ConfigError>> {
+        let requested = self.requested.swap(false, Ordering::SeqCst);
+        let modified = modified(&self.source);
+        if !requested && modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        info!("Reloading config...");
+        Some(self.source.load())
+    }
+
+    /// Resolves on SIGHUP, lets the loop cut its sleep short.
+    pub async fn notified(&self) {
+        self.notify.notified().await
+    }
+
+    #[cfg(unix)]
+    fn listen_sighup(&self) {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Can't listen to SIGHUP: {}", e);
+                return;
+            }
+        };
+        let requested = self.requested.clone();
+        let notify = self.notify.clone();
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                requested.store(true, Ordering::SeqCst);
+                notify.notify_one();
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn listen_sighup(&self) {}
+}
+
+fn modified(source: &ConfigSource) -> Option<SystemTime> {
+    fs::metadata(source.path.as_ref()?).ok()?.modified().ok()
+}
@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use log::{info, warn};
+use crate::{RateFailure, RateStatus};
+
+/// What a sync cycle did, logged once it ends.
+#[derive(Debug, Default)]
+pub struct CycleReport {
+    pub coins_fetched: Option<usize>,
+    pub rates_requested: usize,
+    pub rates_decoded: usize,
+    /// Coins by the status of this cycle's fetch, decode failures aren't counted.
+    pub coverage: BTreeMap<RateStatus, usize>,
+    /// Coins with prices too small or too large for `Decimal`, kept as `ExactNumber`.
+    pub exact_priced: usize,
+    pub failures: Vec<RateFailure>,
+    /// Coins in the probe queue after the cycle.
+    pub backed_off: usize,
+}
+
+impl CycleReport {
+    pub fn log(&self) {
+        if let Some(coins) = self.coins_fetched {
+            info!("Cycle: coin list refreshed, {} coins.", coins);
+        }
+        info!(
+            "Cycle: {} rates requested, {} decoded, {} failed, {} coins backed off.",
+            self.rates_requested,
+            self.rates_decoded,
+            self.failures.len(),
+            self.backed_off
+        );
+        if self.rates_requested > 0 {
+            let coverage: Vec<String> = self
+                .coverage
+                .iter()
+                .map(|(status, num)| {
+                    let percent = *num as f32 * 100_f32 / self.rates_requested as f32;
+                    format!("{:?} {} ({:.1}%)", status, num, percent)
+                })
+                .collect();
+            info!(
+                "Cycle coverage: {}, {} with exact prices.",
+                coverage.join(", "),
+                self.exact_priced
+            );
+        }
+        for x in &self.failures {
+            warn!("Can't decode rate of {}: {} in {}", x.id, x.error, x.raw);
+        }
+    }
+}
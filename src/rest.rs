@@ -3,7 +3,12 @@ use serde::{Serialize, Serializer};
 use bytes::Bytes;
 use hyper::HeaderMap;
 use hyper::header::HeaderValue;
-use crate::{CgCoin, CgRates};
+use crate::chart::{CgRawMarketChart, CgRawOhlc};
+use crate::search::CgSearch;
+use crate::{
+    CgAssetPlatform, CgCoin, CgCoinDetails, CgCoinHistory, CgExchangeRates, CgGlobal, CgMarket,
+    CgRawRates, CgTrending,
+};
 
 #[derive(Clone, PartialEq, strum_macros::EnumString, strum_macros::Display)]
 pub enum RequestMethod {
@@ -16,6 +21,8 @@ pub struct RestApiMethodParam {
     pub key: &'static str,
     pub value: Option<String>,
     pub is_required: bool,
+    /// Values the API accepts, any when `None`.
+    pub allowed: Option<&'static [&'static str]>,
 }
 
 #[allow(dead_code)]
@@ -25,6 +32,7 @@ impl RestApiMethodParam {
             key,
             value: Some(value),
             is_required: true,
+            allowed: None,
         }
     }
     pub fn required(key: &'static str) -> Self {
@@ -32,6 +40,7 @@ impl RestApiMethodParam {
             key,
             value: None,
             is_required: true,
+            allowed: None,
         }
     }
     pub fn optional(key: &'static str) -> Self {
@@ -39,16 +48,52 @@ impl RestApiMethodParam {
             key,
             value: None,
             is_required: false,
+            allowed: None,
+        }
+    }
+    /// Required param taking one of `allowed`.
+    pub fn one_of(key: &'static str, allowed: &'static [&'static str]) -> Self {
+        Self {
+            key,
+            value: None,
+            is_required: true,
+            allowed: Some(allowed),
+        }
+    }
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        match self.allowed {
+            Some(allowed) if !allowed.contains(&value) => Err(format!(
+                "Param '{}' must be one of {}, got '{}'.",
+                self.key,
+                allowed.join(", "),
+                value
+            )),
+            _ => Ok(()),
         }
     }
 }
 
+/// How long a response may be served from `ResponseCache`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CachePolicy {
+    /// As the response headers allow.
+    Http,
+    /// Forever, the response never changes.
+    Permanent,
+}
+
 #[derive(Clone)]
 pub struct RestApiMethodRouteParam {
     pub key: &'static str,
     pub value: Option<String>,
 }
 
+impl RestApiMethodRouteParam {
+    pub fn required(key: &'static str) -> Self {
+        Self { key, value: None }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum ResponseTransformerError {
@@ -117,6 +162,7 @@ pub struct RestApiMethod {
     pub route_params: Vec<RestApiMethodRouteParam>,
     pub transform_response: ResponseTransformer,
     pub configure_request: Option<RequestConfigurator>,
+    pub cache_policy: CachePolicy,
 }
 
 pub type RequestConfigurator = fn(ram: &mut RestApiMethod, params: RequestConfiguratorParams);
@@ -127,10 +173,22 @@ pub type ResponseTransformer = fn(
     headers: &HeaderMap<HeaderValue>,
 ) -> Result<MethodResponse, ResponseTransformerError>;
 
+#[allow(clippy::enum_variant_names)]
 pub enum MethodResponse {
     // CoinGecko
     CgAllCoins(Vec<CgCoin>),
-    CgRates(CgRates),
+    CgRawRates(CgRawRates),
+    CgSupportedVsCurrencies(Vec<String>),
+    CgAssetPlatforms(Vec<CgAssetPlatform>),
+    CgMarkets(Vec<CgMarket>),
+    CgMarketChart(CgRawMarketChart),
+    CgCoinHistory(CgCoinHistory),
+    CgOhlc(CgRawOhlc),
+    CgCoinDetails(CgCoinDetails),
+    CgExchangeRates(CgExchangeRates),
+    CgGlobal(CgGlobal),
+    CgTrending(CgTrending),
+    CgSearch(CgSearch),
 }
 
 #[derive(Clone)]
@@ -143,6 +201,7 @@ pub struct RestApiMethodBuilder {
     route_params: Vec<RestApiMethodRouteParam>,
     transform_response: Option<ResponseTransformer>,
     configure_request: Option<RequestConfigurator>,
+    cache_policy: CachePolicy,
 }
 
 #[allow(dead_code)]
@@ -157,6 +216,7 @@ impl RestApiMethodBuilder {
             route_params: vec![],
             transform_response: None,
             configure_request: None,
+            cache_policy: CachePolicy::Http,
         }
     }
     pub fn set_base_url(&mut self, base_url: String) -> &mut Self {
@@ -191,6 +251,10 @@ impl RestApiMethodBuilder {
         self.configure_request = Some(configure_request);
         self
     }
+    pub fn set_cache_policy(&mut self, cache_policy: CachePolicy) -> &mut Self {
+        self.cache_policy = cache_policy;
+        self
+    }
     pub fn build(&self) -> RestApiMethod {
         RestApiMethod {
             base_url: self
@@ -210,10 +274,12 @@ impl RestApiMethodBuilder {
                 .transform_response
                 .expect("RestApiMethodBuilder transform_response was not set"),
             configure_request: self.configure_request,
+            cache_policy: self.cache_policy,
         }
     }
 }
 
+#[allow(dead_code)]
 impl RestApiMethod {
     pub fn builder() -> RestApiMethodBuilder {
         RestApiMethodBuilder::new()
@@ -223,6 +289,7 @@ impl RestApiMethod {
             if param.value.is_some() {
                 return Err(format!("Param '{}' already set.", key));
             }
+            param.validate(&value)?;
             param.value = Some(value);
             Ok(())
         } else {
@@ -238,6 +305,7 @@ impl RestApiMethod {
             if param.value.is_some() {
                 return Err(format!("Param '{}' already set.", key));
             }
+            param.validate(&value)?;
             param.value = Some(value);
             Ok(())
         } else {
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use rust_decimal::Decimal;
+use tokio::time::{Duration, Instant};
+use log::debug;
+use crate::CgCoin;
+
+#[derive(Clone, Debug)]
+pub enum TierRule {
+    /// Coin id is in the watchlist.
+    Watchlist,
+    /// Known USD market cap is at least the given value. Coins never refreshed match too,
+    /// as the market cap only comes with the first rates fetch.
+    MinMarketCap(Decimal),
+    /// A price was returned for the coin within the given period.
+    PriceSeenWithin(Duration),
+    /// Catch-all for the long tail.
+    All,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tier {
+    pub name: String,
+    pub rule: TierRule,
+    pub interval: Duration,
+}
+
+impl Tier {
+    pub fn new(name: &str, rule: TierRule, interval: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            rule,
+            interval,
+        }
+    }
+}
+
+/// Coins that keep coming back without a price are probed with exponential backoff
+/// instead of their tier interval.
+#[derive(Clone, Debug)]
+pub struct ProbePolicy {
+    /// Consecutive unpriced fetches before a coin is backed off, 0 disables probing.
+    pub after_misses: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+#[derive(Debug)]
+struct Miss {
+    count: u32,
+    probe_at: Option<Instant>,
+}
+
+/// Decides which coins are due for a price refresh. Every coin belongs to the first tier
+/// whose rule matches it, coins matching no tier are never refreshed.
+pub struct Scheduler {
+    tiers: Vec<Tier>,
+    watchlist: HashSet<String>,
+    refreshed_at: HashMap<String, Instant>,
+    price_seen_at: HashMap<String, Instant>,
+    probe: ProbePolicy,
+    misses: HashMap<String, Miss>,
+}
+
+impl Scheduler {
+    pub fn new(tiers: Vec<Tier>, watchlist: HashSet<String>, probe: ProbePolicy) -> Self {
+        Self {
+            tiers,
+            watchlist,
+            refreshed_at: HashMap::new(),
+            price_seen_at: HashMap::new(),
+            probe,
+            misses: HashMap::new(),
+        }
+    }
+
+    /// Replaces tiers, watchlist and probe policy, refresh bookkeeping is kept.
+    pub fn reconfigure(&mut self, tiers: Vec<Tier>, watchlist: HashSet<String>, probe: ProbePolicy) {
+        self.tiers = tiers;
+        self.watchlist = watchlist;
+        self.probe = probe;
+    }
+
+    /// Number of coins in the probe queue.
+    pub fn backed_off_len(&self) -> usize {
+        self.misses.values().filter(|x| x.probe_at.is_some()).count()
+    }
+
+    pub fn tier_of(&self, coin: &CgCoin, now: Instant) -> Option<usize> {
+        self.tiers.iter().position(|tier| match &tier.rule {
+            TierRule::Watchlist => self.watchlist.contains(&coin.id),
+            TierRule::MinMarketCap(min) => match coin.market_cap {
+                Some(x) => x >= *min,
+                None => !self.refreshed_at.contains_key(&coin.id),
+            },
+            TierRule::PriceSeenWithin(period) => self
+                .price_seen_at
+                .get(&coin.id)
+                .is_some_and(|x| now.saturating_duration_since(*x) <= *period),
+            TierRule::All => true,
+        })
+    }
+
+    /// Ids due for refresh, most important tier first, probes last.
+    pub fn due_ids(&self, coins: &[CgCoin], now: Instant) -> Vec<String> {
+        let mut due: Vec<(bool, usize, &String)> = coins
+            .iter()
+            .filter_map(|coin| {
+                let tier = self.tier_of(coin, now)?;
+                if self.due_in(&coin.id, tier, now).is_zero() {
+                    Some((self.probe_at(&coin.id).is_some(), tier, &coin.id))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        due.sort_by_key(|(probe, tier, _)| (*probe, *tier));
+        for (i, tier) in self.tiers.iter().enumerate() {
+            let count = due.iter().filter(|(probe, x, _)| !probe && *x == i).count();
+            if count > 0 {
+                debug!("Tier {}: {} coins due.", tier.name, count);
+            }
+        }
+        let probes = due.iter().filter(|(probe, _, _)| *probe).count();
+        if probes > 0 {
+            debug!("Probing {} of {} backed off coins.", probes, self.backed_off_len());
+        }
+        due.into_iter().map(|(_, _, id)| id.clone()).collect()
+    }
+
+    /// Time until the next coin becomes due, `None` if no coin is scheduled at all.
+    pub fn next_due_in(&self, coins: &[CgCoin], now: Instant) -> Option<Duration> {
+        coins
+            .iter()
+            .filter_map(|coin| {
+                let tier = self.tier_of(coin, now)?;
+                Some(self.due_in(&coin.id, tier, now))
+            })
+            .min()
+    }
+
+    /// Every requested coin waits for its next interval. Coins in `missed` came back without
+    /// a price and may be backed off, the rest, e.g. entries that failed to decode, are neither.
+    pub fn mark_refreshed<'a>(
+        &mut self,
+        requested: &[String],
+        priced: impl Iterator<Item = &'a String>,
+        missed: impl Iterator<Item = &'a String>,
+        now: Instant,
+    ) {
+        let priced: HashSet<&String> = priced.collect();
+        let missed: HashSet<&String> = missed.collect();
+        for id in requested {
+            self.refreshed_at.insert(id.clone(), now);
+            if priced.contains(id) {
+                self.price_seen_at.insert(id.clone(), now);
+                self.misses.remove(id);
+                continue;
+            }
+            if !missed.contains(id) {
+                continue;
+            }
+            let miss = self.misses.entry(id.clone()).or_insert(Miss {
+                count: 0,
+                probe_at: None,
+            });
+            miss.count += 1;
+            let after = self.probe.after_misses;
+            if after > 0 && miss.count >= after {
+                let doublings = 2_u32.saturating_pow(miss.count - after);
+                let backoff = self
+                    .probe
+                    .initial_backoff
+                    .saturating_mul(doublings)
+                    .min(self.probe.max_backoff);
+                miss.probe_at = Some(now + backoff);
+            }
+        }
+    }
+
+    /// Drops bookkeeping of coins that are no longer listed.
+    pub fn retain(&mut self, coins: &[CgCoin]) {
+        let ids: HashSet<&String> = coins.iter().map(|x| &x.id).collect();
+        self.refreshed_at.retain(|id, _| ids.contains(id));
+        self.price_seen_at.retain(|id, _| ids.contains(id));
+        self.misses.retain(|id, _| ids.contains(id));
+    }
+
+    /// Next probe of a backed off coin, watchlisted coins are never backed off.
+    fn probe_at(&self, id: &str) -> Option<Instant> {
+        if self.probe.after_misses == 0 || self.watchlist.contains(id) {
+            return None;
+        }
+        self.misses.get(id)?.probe_at
+    }
+
+    fn due_in(&self, id: &str, tier: usize, now: Instant) -> Duration {
+        if let Some(probe_at) = self.probe_at(id) {
+            return probe_at.saturating_duration_since(now);
+        }
+        match self.refreshed_at.get(id) {
+            Some(x) => self.tiers[tier]
+                .interval
+                .saturating_sub(now.saturating_duration_since(*x)),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_secs(60);
+    const MAX: Duration = Duration::from_secs(300);
+
+    fn scheduler() -> Scheduler {
+        let probe = ProbePolicy {
+            after_misses: 2,
+            initial_backoff: INITIAL,
+            max_backoff: MAX,
+        };
+        let tiers = vec![Tier::new("all", TierRule::All, Duration::from_secs(3600))];
+        Scheduler::new(tiers, HashSet::new(), probe)
+    }
+
+    fn miss(scheduler: &mut Scheduler, id: &String, now: Instant) {
+        scheduler.mark_refreshed(slice::from_ref(id), [].into_iter(), [id].into_iter(), now);
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let mut scheduler = scheduler();
+        let id = "x".to_string();
+        let now = Instant::now();
+        miss(&mut scheduler, &id, now);
+        assert_eq!(scheduler.probe_at(&id), None);
+        miss(&mut scheduler, &id, now);
+        assert_eq!(scheduler.probe_at(&id), Some(now + INITIAL));
+        miss(&mut scheduler, &id, now);
+        assert_eq!(scheduler.probe_at(&id), Some(now + INITIAL * 2));
+        miss(&mut scheduler, &id, now);
+        assert_eq!(scheduler.probe_at(&id), Some(now + INITIAL * 4));
+        assert_eq!(scheduler.backed_off_len(), 1);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut scheduler = scheduler();
+        let id = "x".to_string();
+        let now = Instant::now();
+        for _ in 0..40 {
+            miss(&mut scheduler, &id, now);
+        }
+        assert_eq!(scheduler.probe_at(&id), Some(now + MAX));
+    }
+
+    #[test]
+    fn price_resets_backoff() {
+        let mut scheduler = scheduler();
+        let id = "x".to_string();
+        let now = Instant::now();
+        for _ in 0..3 {
+            miss(&mut scheduler, &id, now);
+        }
+        scheduler.mark_refreshed(slice::from_ref(&id), [&id].into_iter(), [].into_iter(), now);
+        assert_eq!(scheduler.probe_at(&id), None);
+        assert_eq!(scheduler.backed_off_len(), 0);
+        miss(&mut scheduler, &id, now);
+        assert_eq!(scheduler.probe_at(&id), None);
+    }
+
+    #[test]
+    fn undecoded_is_not_a_miss() {
+        let mut scheduler = scheduler();
+        let id = "x".to_string();
+        let now = Instant::now();
+        for _ in 0..3 {
+            scheduler.mark_refreshed(slice::from_ref(&id), [].into_iter(), [].into_iter(), now);
+        }
+        assert_eq!(scheduler.probe_at(&id), None);
+    }
+}
@@ -0,0 +1,195 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::CgCoin;
+
+/// How a hit matched the query, better first.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /// Query is the symbol, e.g. `eth`.
+    Symbol,
+    /// Query is the whole name or id, e.g. `wrapped bitcoin`.
+    Exact,
+    /// Name, id, symbol or a word of the name starts with the query.
+    Prefix,
+    /// Name, id or a word of the name is a typo or two away from the query.
+    Fuzzy,
+    /// Found by `/search` only, e.g. a coin outside the local set.
+    Remote,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SearchHit {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    pub market_cap: Option<Decimal>,
+    /// Only `/search` returns it.
+    pub market_cap_rank: Option<u32>,
+    pub matched: MatchKind,
+}
+
+/// `/search` response, exchanges, categories and NFTs are ignored.
+#[derive(Deserialize, Debug)]
+pub struct CgSearch {
+    #[serde(default)]
+    coins: Vec<CgSearchCoin>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CgSearchCoin {
+    id: String,
+    name: String,
+    symbol: String,
+    market_cap_rank: Option<u32>,
+}
+
+impl CgSearch {
+    /// Hits in CoinGecko's order, unranked coins last.
+    pub fn into_hits(self) -> Vec<SearchHit> {
+        let mut hits: Vec<SearchHit> = self
+            .coins
+            .into_iter()
+            .map(|x| SearchHit {
+                id: x.id,
+                symbol: x.symbol.to_lowercase(),
+                name: x.name,
+                market_cap: None,
+                market_cap_rank: x.market_cap_rank,
+                matched: MatchKind::Remote,
+            })
+            .collect();
+        hits.sort_by_key(|x| x.market_cap_rank.is_none());
+        hits
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Symbol,
+    Name,
+    Id,
+    Word,
+}
+
+/// Offline index over the names, symbols and ids of coins. Ids read as names with
+/// dashes for spaces, so `wrapped-bitcoin` is found by `wrapped bitcoin`.
+pub struct SearchIndex {
+    coins: Vec<SearchHit>,
+    terms: BTreeMap<String, Vec<(usize, Field)>>,
+}
+
+impl SearchIndex {
+    pub fn new(coins: &[CgCoin]) -> Self {
+        let mut terms: BTreeMap<String, Vec<(usize, Field)>> = BTreeMap::new();
+        for (i, coin) in coins.iter().enumerate() {
+            let name = normalize(&coin.name);
+            let mut fields = vec![
+                (normalize(&coin.symbol), Field::Symbol),
+                (normalize(&coin.id), Field::Id),
+            ];
+            if name.contains(' ') {
+                fields.extend(name.split(' ').map(|x| (x.to_string(), Field::Word)));
+            }
+            fields.push((name, Field::Name));
+            for (term, field) in fields {
+                if term.is_empty() {
+                    continue;
+                }
+                let postings = terms.entry(term).or_default();
+                if !postings.contains(&(i, field)) {
+                    postings.push((i, field));
+                }
+            }
+        }
+        let coins = coins
+            .iter()
+            .map(|x| SearchHit {
+                id: x.id.clone(),
+                symbol: x.symbol.to_lowercase(),
+                name: x.name.clone(),
+                market_cap: x.market_cap,
+                market_cap_rank: None,
+                matched: MatchKind::Exact,
+            })
+            .collect();
+        Self { coins, terms }
+    }
+
+    pub fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    /// Every coin matching `query`, ranked. Queries under 4 characters aren't matched fuzzily.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let mut best: HashMap<usize, MatchKind> = HashMap::new();
+        let mut found = |i: usize, kind: MatchKind| {
+            let x = best.entry(i).or_insert(kind);
+            *x = (*x).min(kind);
+        };
+        for (term, postings) in self.terms.range(query.clone()..) {
+            if !term.starts_with(&query) {
+                break;
+            }
+            for (i, field) in postings {
+                let kind = match field {
+                    Field::Symbol if *term == query => MatchKind::Symbol,
+                    Field::Name | Field::Id if *term == query => MatchKind::Exact,
+                    _ => MatchKind::Prefix,
+                };
+                found(*i, kind);
+            }
+        }
+        let max_edits = match query.chars().count() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+        if max_edits > 0 {
+            let len = query.chars().count();
+            for (term, postings) in &self.terms {
+                if term.chars().count().abs_diff(len) > max_edits
+                    || strsim::osa_distance(term, &query) > max_edits
+                {
+                    continue;
+                }
+                for (i, field) in postings {
+                    if *field != Field::Symbol {
+                        found(*i, MatchKind::Fuzzy);
+                    }
+                }
+            }
+        }
+        let mut hits: Vec<SearchHit> = best
+            .into_iter()
+            .map(|(i, kind)| SearchHit {
+                matched: kind,
+                ..self.coins[i].clone()
+            })
+            .collect();
+        rank(&mut hits);
+        hits
+    }
+}
+
+/// By match kind, then market cap with unknown ones last, then id.
+pub fn rank(hits: &mut [SearchHit]) {
+    hits.sort_by(|a, b| {
+        (a.matched, Reverse(a.market_cap), &a.id).cmp(&(b.matched, Reverse(b.market_cap), &b.id))
+    });
+}
+
+/// Lowercase words of letters and digits separated by single spaces.
+fn normalize(x: &str) -> String {
+    x.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicI32, Ordering};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_util::sync::CancellationToken;
+use log::{info, warn};
+
+pub const CANCELLED: &str = "Cancelled.";
+/// 128 + SIGINT.
+const SIGINT_EXIT_CODE: i32 = 130;
+/// Exit code of the first signal received, 128 + its number.
+static INTERRUPTED_EXIT_CODE: AtomicI32 = AtomicI32::new(SIGINT_EXIT_CODE);
+/// How long an in-flight request may take after shutdown was requested.
+pub const SHUTDOWN_DEADLINE_SEC: u64 = 10;
+
+/// Cancels the token on the first SIGINT/SIGTERM, exits right away on the second one.
+pub fn listen_shutdown_signals(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        INTERRUPTED_EXIT_CODE.store(wait_signal().await, Ordering::SeqCst);
+        info!("Shutting down, send the signal again to exit immediately...");
+        cancel.cancel();
+        let exit_code = wait_signal().await;
+        warn!("Forced exit.");
+        std::process::exit(exit_code);
+    });
+}
+
+/// Exit code of a run stopped by a signal: 130 for SIGINT, 143 for SIGTERM.
+pub fn interrupted_exit_code() -> i32 {
+    INTERRUPTED_EXIT_CODE.load(Ordering::SeqCst)
+}
+
+/// Waits for SIGINT/SIGTERM, returns 128 + the signal number.
+#[cfg(unix)]
+async fn wait_signal() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => SIGINT_EXIT_CODE,
+        _ = sigterm.recv() => 143,
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() -> i32 {
+    tokio::signal::ctrl_c().await.unwrap();
+    SIGINT_EXIT_CODE
+}
+
+/// Runs `fut`, once cancelled it gets `SHUTDOWN_DEADLINE_SEC` more to finish before it is dropped.
+pub async fn finish_within_deadline<F: Future>(
+    cancel: &CancellationToken,
+    fut: F,
+) -> Result<F::Output, String> {
+    tokio::pin!(fut);
+    tokio::select! {
+        res = &mut fut => Ok(res),
+        _ = cancel.cancelled() => match timeout(Duration::from_secs(SHUTDOWN_DEADLINE_SEC), fut).await {
+            Ok(res) => Ok(res),
+            Err(_) => Err(CANCELLED.to_string()),
+        },
+    }
+}
+
+/// Sleeps unless cancelled, `Err` when the sleep was cut short.
+pub async fn sleep_or_cancel(cancel: &CancellationToken, duration: Duration) -> Result<(), String> {
+    tokio::select! {
+        _ = sleep(duration) => Ok(()),
+        _ = cancel.cancelled() => Err(CANCELLED.to_string()),
+    }
+}
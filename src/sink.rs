@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::PathBuf;
+use log::info;
+use serde::Serialize;
+use tokio::time::Instant;
+use crate::config::SinksConfig;
+use crate::registry::{CoinMetadata, CoinRegistry};
+use crate::CgCoin;
+
+/// Coin of the JSON snapshot, `metadata` only when the registry has it.
+#[derive(Serialize)]
+struct SnapshotCoin<'a> {
+    #[serde(flatten)]
+    coin: &'a CgCoin,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a CoinMetadata>,
+}
+
+/// Destination of the coin snapshot written after every rates pass.
+pub enum Sink {
+    /// Logs a few example coins, stdout is left to command output.
+    Log,
+    /// Replaces the file with the whole snapshot, via a temporary file and rename.
+    JsonFile(PathBuf),
+}
+
+impl Sink {
+    pub fn write(&self, coins: &[CgCoin], registry: &CoinRegistry) -> Result<(), String> {
+        match self {
+            Sink::Log => {
+                info!("Example:");
+                for i in [0, 10, 100, 1000, 10000] {
+                    if let Some(coin) = coins.get(i) {
+                        info!("{:?}", coin);
+                    }
+                }
+                Ok(())
+            }
+            Sink::JsonFile(path) => {
+                let tmp = path.with_extension("tmp");
+                let now = Instant::now();
+                let snapshot: Vec<SnapshotCoin> = coins
+                    .iter()
+                    .map(|coin| SnapshotCoin {
+                        coin,
+                        metadata: registry.get(&coin.id, now),
+                    })
+                    .collect();
+                let content = serde_json::to_vec(&snapshot).unwrap();
+                fs::write(&tmp, content)
+                    .and_then(|_| fs::rename(&tmp, path))
+                    .map_err(|e| format!("Can't write snapshot {}: {}", path.display(), e))
+            }
+        }
+    }
+}
+
+pub fn create_sinks(config: &SinksConfig) -> Vec<Sink> {
+    let mut sinks = vec![];
+    if config.log {
+        sinks.push(Sink::Log);
+    }
+    if let Some(path) = &config.json_file {
+        sinks.push(Sink::JsonFile(path.clone()));
+    }
+    sinks
+}
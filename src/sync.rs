@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use log::{info, warn};
+use crate::cache::ResponseCache;
+use crate::config::{Config, StalePolicy};
+use crate::reload::ConfigWatcher;
+use crate::registry::CoinRegistry;
+use crate::report::CycleReport;
+use crate::filter::CoinFilter;
+use crate::hyper::HyperClient;
+use crate::rest::RestApiMethodBuilder;
+use crate::scheduler::Scheduler;
+use crate::shutdown::sleep_or_cancel;
+use crate::sink::{create_sinks, Sink};
+use crate::{
+    build_all_coins_rest_api_method_builder_template,
+    build_coin_rates_rest_api_method_builder_template, check_vs_currencies, fetch_coins,
+    fetch_coin_metadata, fetch_rates, CgCoin, RateStatus,
+    RequestPacing,
+};
+
+const MIN_RATES_TICK_SEC: f32 = 1_f32;
+
+/// Coin set kept between list refreshes, rate refreshes are tracked by the scheduler.
+pub struct SyncState {
+    pub coins: Vec<CgCoin>,
+    pub coins_synced_at: Option<Instant>,
+    pub scheduler: Scheduler,
+    pub registry: CoinRegistry,
+}
+
+impl SyncState {
+    pub fn new(scheduler: Scheduler, registry: CoinRegistry) -> Self {
+        Self {
+            coins: vec![],
+            coins_synced_at: None,
+            scheduler,
+            registry,
+        }
+    }
+}
+
+/// Everything derived from the config plus the shared client and cache.
+pub struct SyncContext<'a> {
+    pub hyper: &'a HyperClient,
+    pub cache: &'a mut dyn ResponseCache,
+    pub pacing: RequestPacing,
+    pub all_coins_rest_api_method_template: RestApiMethodBuilder,
+    pub coin_rates_rest_api_method_template: RestApiMethodBuilder,
+    pub filter: CoinFilter,
+    pub sinks: Vec<Sink>,
+    pub config: Config,
+    pub cancel: CancellationToken,
+}
+
+impl<'a> SyncContext<'a> {
+    pub fn new(
+        hyper: &'a HyperClient,
+        cache: &'a mut dyn ResponseCache,
+        config: Config,
+        cancel: CancellationToken,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            hyper,
+            cache,
+            pacing: RequestPacing::new(&config.client),
+            all_coins_rest_api_method_template: build_all_coins_rest_api_method_builder_template(
+                &config.client,
+            ),
+            coin_rates_rest_api_method_template: build_coin_rates_rest_api_method_builder_template(
+                &config.client,
+                &requested_vs_currencies(&config),
+            ),
+            filter: CoinFilter::new(&config.filter)?,
+            sinks: create_sinks(&config.sinks),
+            config,
+            cancel,
+        })
+    }
+
+    pub fn create_scheduler(&self) -> Scheduler {
+        Scheduler::new(
+            self.config.scheduler.tiers(),
+            self.config.scheduler.watchlist(),
+            self.config.scheduler.probe(),
+        )
+    }
+
+    pub async fn check_vs_currencies(&mut self, config: &Config) -> Result<(), String> {
+        check_vs_currencies(
+            self.hyper,
+            &config.client,
+            &config.rates.vs_currencies,
+            self.cache,
+            &mut self.pacing,
+            &self.cancel,
+        )
+            .await
+    }
+
+    /// Swaps in an already validated config between cycles.
+    pub fn apply_config(&mut self, config: Config, state: &mut SyncState) -> Result<(), String> {
+        let filter = CoinFilter::new(&config.filter)?;
+        if config.client.cache_dir != self.config.client.cache_dir
+            || config.client.cache_max_entries != self.config.client.cache_max_entries
+        {
+            warn!("client.cache_dir and client.cache_max_entries changes take effect after restart.");
+        }
+        if config.client != self.config.client || config.rates != self.config.rates {
+            self.all_coins_rest_api_method_template =
+                build_all_coins_rest_api_method_builder_template(&config.client);
+            self.coin_rates_rest_api_method_template =
+                build_coin_rates_rest_api_method_builder_template(
+                    &config.client,
+                    &requested_vs_currencies(&config),
+                );
+            if config.client.sleep_between_requests_initial_ms
+                != self.config.client.sleep_between_requests_initial_ms
+            {
+                self.pacing = RequestPacing::new(&config.client);
+            }
+            self.pacing.step_ms = config.client.sleep_between_requests_step_ms;
+        }
+        if config.metadata != self.config.metadata {
+            state.registry.set_ttl(config.metadata.ttl());
+        }
+        if config.rates.vs_currencies != self.config.rates.vs_currencies {
+            let vs_currencies = &config.rates.vs_currencies;
+            for coin in state.coins.iter_mut() {
+                coin.rates.retain(|x, _| vs_currencies.contains(x));
+                coin.exact_rates.retain(|x, _| vs_currencies.contains(x));
+            }
+        }
+        if config.filter != self.config.filter {
+            // Coins dropped by the old filter are gone, the list has to be fetched again.
+            state.coins_synced_at = None;
+        }
+        if config.scheduler != self.config.scheduler {
+            state.scheduler.reconfigure(
+                config.scheduler.tiers(),
+                config.scheduler.watchlist(),
+                config.scheduler.probe(),
+            );
+        }
+        self.filter = filter;
+        self.sinks = create_sinks(&config.sinks);
+        self.config = config;
+        Ok(())
+    }
+}
+
+pub async fn run(ctx: &mut SyncContext<'_>, watcher: &mut ConfigWatcher) -> Result<(), String> {
+    let config = ctx.config.clone();
+    ctx.check_vs_currencies(&config).await?;
+    let mut state = SyncState::new(ctx.create_scheduler(), CoinRegistry::new(ctx.config.metadata.ttl()));
+    loop {
+        match watcher.poll() {
+            Some(Ok(config)) => {
+                let res = if config.rates.vs_currencies != ctx.config.rates.vs_currencies {
+                    ctx.check_vs_currencies(&config).await
+                } else {
+                    Ok(())
+                };
+                match res.and_then(|_| ctx.apply_config(config, &mut state)) {
+                    Ok(()) => info!("Config reloaded."),
+                    Err(e) if ctx.cancel.is_cancelled() => return Err(e),
+                    Err(e) => warn!("Config reload failed, keeping the current one: {}", e),
+                }
+            }
+            Some(Err(e)) => warn!("Config reload failed, keeping the current one: {}", e),
+            None => {}
+        }
+        let coins_refresh_sec = ctx.config.scheduler.coins_refresh_sec;
+        if let Err(e) = run_cycle(ctx, &mut state).await {
+            let flushed = shutdown(ctx, &state);
+            if ctx.cancel.is_cancelled() {
+                return flushed;
+            }
+            return Err(e);
+        }
+        let mut wait = next_due_in(state.coins_synced_at, coins_refresh_sec);
+        if let Some(rates_wait) = state.scheduler.next_due_in(&state.coins, Instant::now()) {
+            wait = wait.min(rates_wait.max(Duration::from_secs_f32(MIN_RATES_TICK_SEC)));
+        }
+        info!("Done, sleeping {} seconds to repeat...", wait.as_secs_f32());
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = watcher.notified() => {}
+            _ = ctx.cancel.cancelled() => return shutdown(ctx, &state),
+        }
+    }
+}
+
+async fn run_cycle(ctx: &mut SyncContext<'_>, state: &mut SyncState) -> Result<(), String> {
+    let mut report = CycleReport::default();
+    if is_due(state.coins_synced_at, ctx.config.scheduler.coins_refresh_sec) {
+        sync_coins(ctx, state).await?;
+        report.coins_fetched = Some(state.coins.len());
+    }
+    let due_ids = state.scheduler.due_ids(&state.coins, Instant::now());
+    let rates_synced = !due_ids.is_empty();
+    if rates_synced {
+        let res = sync_rates(ctx, state, due_ids, &mut report).await;
+        report.backed_off = state.scheduler.backed_off_len();
+        report.log();
+        res?;
+    }
+    // Metadata only takes the time left until the next coins or rates are due.
+    let now = Instant::now();
+    let mut until = now + next_due_in(state.coins_synced_at, ctx.config.scheduler.coins_refresh_sec);
+    if let Some(rates_wait) = state.scheduler.next_due_in(&state.coins, now) {
+        until = until.min(now + rates_wait);
+    }
+    sync_metadata(ctx, state, Some(until)).await?;
+    if rates_synced {
+        write_sinks(ctx, state);
+    }
+    Ok(())
+}
+
+/// Flushes whatever was synced so far, rates merged before the cancellation or error included.
+fn shutdown(ctx: &SyncContext<'_>, state: &SyncState) -> Result<(), String> {
+    if !state.coins.is_empty() {
+        info!("Flushing the last snapshot...");
+        write_sinks(ctx, state);
+    }
+    info!("Sync stopped.");
+    Ok(())
+}
+
+/// Single cycle: the coin list, rates of every scheduled coin and due metadata, written
+/// to the sinks like a daemon cycle.
+pub async fn run_once(ctx: &mut SyncContext<'_>) -> Result<SyncState, String> {
+    let config = ctx.config.clone();
+    ctx.check_vs_currencies(&config).await?;
+    let mut state = SyncState::new(ctx.create_scheduler(), CoinRegistry::new(ctx.config.metadata.ttl()));
+    let mut report = CycleReport::default();
+    sync_coins(ctx, &mut state).await?;
+    report.coins_fetched = Some(state.coins.len());
+    let due_ids = state.scheduler.due_ids(&state.coins, Instant::now());
+    let res = sync_rates(ctx, &mut state, due_ids, &mut report).await;
+    report.backed_off = state.scheduler.backed_off_len();
+    report.log();
+    res?;
+    sync_metadata(ctx, &mut state, None).await?;
+    write_sinks(ctx, &state);
+    Ok(state)
+}
+
+/// Replaces the coin set with the filtered list, carrying known rates over to the coins that are still listed.
+pub async fn sync_coins(ctx: &mut SyncContext<'_>, state: &mut SyncState) -> Result<(), String> {
+    let coins = fetch_coins(
+        ctx.hyper,
+        &ctx.all_coins_rest_api_method_template,
+        ctx.cache,
+        &mut ctx.pacing,
+        &ctx.cancel,
+    )
+        .await
+        .map_err(|e| format!("fetch_coins error: {}", e))?;
+    let fetched_len = coins.len();
+    let mut coins = ctx.filter.apply(coins);
+    info!("Fetched {} tokens, {} pass filters.", fetched_len, coins.len());
+    let mut known: HashMap<String, CgCoin> = state
+        .coins
+        .drain(..)
+        .map(|x| (x.id.clone(), x))
+        .collect();
+    for coin in coins.iter_mut() {
+        if let Some(known) = known.remove(&coin.id) {
+            coin.rates = known.rates;
+            coin.exact_rates = known.exact_rates;
+            coin.market_cap = known.market_cap;
+            coin.rates_updated_at = known.rates_updated_at;
+            coin.rates_fetched_at = known.rates_fetched_at;
+            coin.rate_status = known.rate_status;
+        }
+    }
+    state.scheduler.retain(&coins);
+    state.registry.retain(&coins);
+    state.coins = coins;
+    state.coins_synced_at = Some(Instant::now());
+    Ok(())
+}
+
+/// Fetches rates of the given ids, batched in the order the scheduler returned them.
+/// Entries that fail to decode go to `report`, the rest of their batch is merged.
+pub async fn sync_rates(
+    ctx: &mut SyncContext<'_>,
+    state: &mut SyncState,
+    ids: Vec<String>,
+    report: &mut CycleReport,
+) -> Result<(), String> {
+    let coins = &mut state.coins;
+    let ids_len = ids.len();
+    let positions: HashMap<String, usize> = coins
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.id.clone(), i))
+        .collect();
+    let mut rates_found_num = 0;
+    for chunk in ids.chunks(ctx.config.client.rates_tokens_per_request) {
+        info!(
+            "Sleeping {}ms between requests...",
+            ctx.pacing.sleep_between_requests_ms
+        );
+        sleep_or_cancel(&ctx.cancel, Duration::from_millis(ctx.pacing.sleep_between_requests_ms))
+            .await?;
+        let fetched = fetch_rates(
+            ctx.hyper,
+            &ctx.coin_rates_rest_api_method_template,
+            chunk.to_vec(),
+            ctx.cache,
+            &ctx.config.numeric,
+            &mut ctx.pacing,
+            &ctx.cancel,
+        )
+            .await
+            .map_err(|e| format!("fetch_rates error: {}", e))?;
+        let rates = fetched.rates;
+        rates_found_num += rates.len();
+        report.rates_requested += chunk.len();
+        report.rates_decoded += rates.len();
+        let failed: HashSet<String> = fetched.failures.iter().map(|x| x.id.clone()).collect();
+        report.failures.extend(fetched.failures);
+        info!(
+            "Fetched {} ({}) rates of {} tokens.",
+            rates.len(),
+            rates_found_num,
+            ids_len
+        );
+        let stale_after = ctx.config.rates.stale_after();
+        let vs_currencies = &ctx.config.rates.vs_currencies;
+        let drop_stale = ctx.config.rates.stale_policy == StalePolicy::Drop;
+        let mut priced = vec![];
+        let mut missed = vec![];
+        for id in chunk {
+            if failed.contains(id) {
+                continue;
+            }
+            let Some(i) = positions.get(id) else {
+                continue;
+            };
+            let coin = &mut coins[*i];
+            let Some(rate) = rates.get(id) else {
+                coin.rate_status = Some(RateStatus::NotReturned);
+                *report.coverage.entry(RateStatus::NotReturned).or_default() += 1;
+                missed.push(id);
+                continue;
+            };
+            if let Some(market_cap) = rate.market_caps.get("usd") {
+                coin.market_cap = Some(*market_cap);
+            }
+            coin.rates_updated_at = rate.last_updated_at;
+            coin.rates_fetched_at = Some(rate.fetched_at);
+            let stale = stale_after.is_some_and(|x| rate.is_stale(x));
+            let status = if stale { RateStatus::Stale } else { rate.status() };
+            coin.rate_status = Some(status);
+            *report.coverage.entry(status).or_default() += 1;
+            // A returned rate replaces every price, currencies now `null`, zero or no
+            // longer quoted are gone.
+            if stale && drop_stale {
+                coin.rates.clear();
+                coin.exact_rates.clear();
+            } else {
+                // `usd` may only be there for the market cap.
+                let configured = |x: &String| vs_currencies.contains(x);
+                coin.rates = rate
+                    .prices
+                    .iter()
+                    .filter(|(vs_currency, price)| !price.is_zero() && configured(vs_currency))
+                    .map(|(vs_currency, price)| (vs_currency.clone(), *price))
+                    .collect();
+                coin.exact_rates = rate
+                    .exact_prices()
+                    .filter(|(vs_currency, _)| configured(vs_currency))
+                    .map(|(vs_currency, price)| (vs_currency.clone(), price.clone()))
+                    .collect();
+                if !coin.exact_rates.is_empty() {
+                    report.exact_priced += 1;
+                }
+            }
+            match rate.status() {
+                RateStatus::Priced => priced.push(id),
+                RateStatus::NullReported | RateStatus::ZeroReported => missed.push(id),
+                RateStatus::NotReturned | RateStatus::Stale => {}
+            }
+        }
+        let now = Instant::now();
+        state.scheduler.mark_refreshed(chunk, priced.into_iter(), missed.into_iter(), now);
+    }
+    Ok(())
+}
+
+/// Fetches `/coins/{id}` of up to `metadata.per_cycle` wanted coins whose metadata is missing
+/// or expired, stopping before a request would start after `until`. A coin that fails or
+/// doesn't fit is retried next cycle.
+async fn sync_metadata(
+    ctx: &mut SyncContext<'_>,
+    state: &mut SyncState,
+    until: Option<Instant>,
+) -> Result<(), String> {
+    let config = &ctx.config.metadata;
+    if config.per_cycle == 0 {
+        return Ok(());
+    }
+    let wanted = if config.ids.is_empty() {
+        ctx.config.scheduler.watchlist.clone()
+    } else {
+        config.ids.clone()
+    };
+    let listed: HashSet<&String> = state.coins.iter().map(|x| &x.id).collect();
+    let wanted: Vec<String> = wanted.into_iter().filter(|x| listed.contains(x)).collect();
+    let mut due_ids = state.registry.due_ids(&wanted, Instant::now());
+    due_ids.truncate(config.per_cycle);
+    let mut fetched_num = 0;
+    let due_num = due_ids.len();
+    for (i, id) in due_ids.into_iter().enumerate() {
+        let sleep = Duration::from_millis(ctx.pacing.sleep_between_requests_ms);
+        if until.is_some_and(|x| Instant::now() + sleep >= x) {
+            info!("Rates are due, {} metadata fetches postponed.", due_num - i);
+            break;
+        }
+        sleep_or_cancel(&ctx.cancel, sleep).await?;
+        match fetch_coin_metadata(ctx, &id).await {
+            Ok(metadata) => {
+                state.registry.insert(metadata, Instant::now());
+                fetched_num += 1;
+            }
+            Err(e) if ctx.cancel.is_cancelled() => return Err(e),
+            Err(e) => warn!("Can't fetch metadata of {}: {}", id, e),
+        }
+    }
+    if fetched_num > 0 {
+        info!("Fetched metadata of {} coins.", fetched_num);
+    }
+    Ok(())
+}
+
+fn is_due(synced_at: Option<Instant>, refresh_sec: f32) -> bool {
+    next_due_in(synced_at, refresh_sec).is_zero()
+}
+
+fn next_due_in(synced_at: Option<Instant>, refresh_sec: f32) -> Duration {
+    match synced_at {
+        Some(x) => Duration::from_secs_f32(refresh_sec).saturating_sub(x.elapsed()),
+        None => Duration::ZERO,
+    }
+}
+
+/// `rates.vs_currencies` plus `usd`, the market cap the scheduler tiers read.
+fn requested_vs_currencies(config: &Config) -> Vec<String> {
+    let mut vs_currencies = config.rates.vs_currencies.clone();
+    if !vs_currencies.iter().any(|x| x == "usd") {
+        vs_currencies.push("usd".to_string());
+    }
+    vs_currencies
+}
+
+fn write_sinks(ctx: &SyncContext<'_>, state: &SyncState) {
+    for sink in &ctx.sinks {
+        if let Err(e) = sink.write(&state.coins, &state.registry) {
+            warn!("{}", e);
+        }
+    }
+}